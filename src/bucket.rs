@@ -1,13 +1,17 @@
 use types::pgid_t;
 use tx::Tx;
 use node::Node;
-use cursor::Cursor;
-use page::{Page, BUCKET_LEAF_FLAG};
+use cursor::{Cursor, Iter, KeyRange};
+use errors::Error;
+use page::{self, Page, BRANCH_PAGE_FLAG, LEAF_PAGE_FLAG, BUCKET_LEAF_FLAG};
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Weak;
+use std::mem;
+use std::ptr;
+use std::slice;
 
 // MAX_KEY_SIZE is the maximum length of a key, in bytes
 pub const MAX_KEY_SIZE: u32 = 32768;
@@ -21,113 +25,149 @@ pub const MAX_FILL_PERCENT: f32 = 1.0;
 // This value can be changed by setting Bucket.FillPercent.
 pub const DEFAULT_FILL_PERCENT: f32 = 0.5;
 
+// BUCKET_HEADER_SIZE is the size of the bucket header stored at the start of a bucket value.
+pub const BUCKET_HEADER_SIZE: usize = mem::size_of::<_Bucket>();
+
 // Bucket represents a collection of key/value pairs inside the datasbase.
 pub struct Bucket<'a> {
-    pub bucket: Box<_Bucket>,
-    pub tx: Rc<RefCell<Tx>>,                           // the associated transcation
-    buckets: HashMap<&'static str, Rc<RefCell<Bucket<'a>>>>,        // subbucket cache
-    page: Option<Rc<RefCell<Page>>>,                   // inline page reference
-    pub root_node: Option<Rc<RefCell<Node<'a>>>>,      // materialized node for the root page.
-    pub nodes: HashMap<pgid_t, Rc<RefCell<Node<'a>>>>, // node cache
+    pub bucket: RefCell<_Bucket>,
+    tx: &'a Tx,                                                 // the associated transcation
+    buckets: RefCell<HashMap<Vec<u8>, Rc<Bucket<'a>>>>,        // subbucket cache
+    page: Cell<Option<&'a Page>>,                               // inline page reference
+    pub root_node: RefCell<Option<Rc<RefCell<Node<'a>>>>>,      // materialized node for the root page.
+    pub nodes: RefCell<HashMap<pgid_t, Rc<RefCell<Node<'a>>>>>, // node cache
 
     // Sets the threshold for filling nodes when they split. By default,
     // the bucket will fill to 50% but it can be useful to increase this
     // amount if you know that your write workloads are mostly append-only.
     //
     // This is non-persisted across transactions so it must be set in every Tx.
-    pub fill_percent: f32,
-    pub weak_self: Weak<RefCell<Bucket<'a>>>, // weak pointer to self
+    pub fill_percent: Cell<f32>,
+    pub weak_self: RefCell<Weak<Bucket<'a>>>, // weak pointer to self
 }
 
 impl<'a> Bucket<'a> {
-    pub fn new(b: Box<_Bucket>, tx: &Rc<RefCell<Tx>>) -> Bucket<'a> {
+    pub(crate) fn new(b: _Bucket, tx: &'a Tx) -> Bucket<'a> {
         Bucket {
-            bucket: b,
-            tx: Rc::clone(tx),
-            buckets: HashMap::new(),
-            page: None,
-            root_node: None,
-            nodes: HashMap::new(),
-            fill_percent: 0.0,
-            weak_self: Weak::new(),
+            bucket: RefCell::new(b),
+            tx: tx,
+            buckets: RefCell::new(HashMap::new()),
+            page: Cell::new(None),
+            root_node: RefCell::new(None),
+            nodes: RefCell::new(HashMap::new()),
+            fill_percent: Cell::new(DEFAULT_FILL_PERCENT),
+            weak_self: RefCell::new(Weak::new()),
         }
     }
 
-    pub fn new_rc_refcell(b: Box<_Bucket>, tx: &Rc<RefCell<Tx>>) -> Rc<RefCell<Bucket<'a>>> {
-        let b = Rc::new(RefCell::new(Bucket::new(b, tx)));
-        b.borrow_mut().weak_self = Rc::downgrade(&b);
+    pub(crate) fn new_rc(b: _Bucket, tx: &'a Tx) -> Rc<Bucket<'a>> {
+        let b = Rc::new(Bucket::new(b, tx));
+        *b.weak_self.borrow_mut() = Rc::downgrade(&b);
         b
     }
 
+    fn to_rc(&self) -> Rc<Bucket<'a>> {
+        self.weak_self.borrow().upgrade().unwrap()
+    }
+
     // node creates a node from a page and associates it with a given parent.
-    pub fn node(
-        &mut self,
+    pub(crate) fn node(
+        &self,
         pgid: pgid_t,
         parent: Option<Rc<RefCell<Node<'a>>>>,
     ) -> Rc<RefCell<Node<'a>>> {
         // Retrieve node if it's already been created.
-        if let Some(ref n) = self.nodes.get(&pgid) {
+        if let Some(n) = self.nodes.borrow().get(&pgid) {
             return Rc::clone(n);
         }
 
         // Otherwise create a node and cache it.
-        let n = Rc::new(RefCell::new(
-            Node::new(Rc::clone(&self.weak_self.upgrade().unwrap()))
-        ));
+        let n = Rc::new(RefCell::new(Node::new(self.to_rc())));
+        n.borrow_mut().weak_self = Rc::downgrade(&n);
 
         if let Some(ref p) = parent {
-            let mut parent_node = p.borrow_mut();
-            parent_node.append_child(&n);
+            p.borrow().append_child(&n);
             n.borrow_mut().set_parent(Rc::downgrade(p));
         } else {
-            self.root_node = Some(Rc::clone(&n));
+            *self.root_node.borrow_mut() = Some(Rc::clone(&n));
         }
 
-        // use the inline page if this is an inline bucket.
-        let p = &mut self.page;
-        if p.is_none() {
-            *p = Some(Rc::clone(&self.tx.borrow().page(pgid)));
-        }
+        // Use the inline page if this is an inline bucket.
+        let p = match self.page.get() {
+            Some(p) => p,
+            None => self.tx().page(pgid),
+        };
 
         // Read the page into the node and cache it.
-        // n.read(p);
-        self.nodes.insert(pgid, Rc::clone(&n));
+        n.borrow_mut().read(p);
+        self.nodes.borrow_mut().insert(pgid, Rc::clone(&n));
 
         // Update statistics
-        self.tx.borrow_mut().stats.node_count += 1;
+        self.tx().stats.borrow_mut().node_count += 1;
 
         n
     }
 
-    pub fn tx(&self) -> &Rc<RefCell<Tx>> {
-        &self.tx
+    // tx returns the transaction of the bucket.
+    pub fn tx(&self) -> &'a Tx {
+        self.tx
     }
 
     // returns the root of the bucket
     pub fn root(&self) -> pgid_t {
-        self.bucket.root
+        self.bucket.borrow().root
     }
 
     pub fn writable(&self) -> bool {
-        self.tx.borrow().writable
+        self.tx().writable
+    }
+
+    // check_writable returns an error if the transaction is closed or read-only.
+    pub(crate) fn check_writable(&self) -> Result<(), Error> {
+        if self.tx().db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        } else if !self.writable() {
+            return Err(Error::TxNotWritable);
+        }
+        Ok(())
     }
 
     // creates a cursor associated with the bucket.
     // The cursor is only valid as long as the transaction is open.
     // Do not use a cursor after the transaction is closed.
-    pub fn cursor(&mut self) -> Rc<RefCell<Cursor<'a>>> {
+    pub fn cursor(&self) -> Rc<RefCell<Cursor<'a>>> {
         // update transaction statistics.
-        self.tx.borrow_mut().stats.cursor_count += 1;
+        self.tx().stats.borrow_mut().cursor_count += 1;
 
         // Allocate and return a cursor.
-        Rc::new(RefCell::new(Cursor::new(&self.weak_self.upgrade().unwrap())))
+        Rc::new(RefCell::new(Cursor::new(&self.to_rc())))
+    }
+
+    // iter returns an iterator over the key/value pairs in the bucket in sorted order.
+    // It can also be walked in reverse. Nested buckets are returned with a nil value.
+    // The returned keys and values are only valid for the life of the transaction.
+    pub fn iter(&self) -> Iter<'a> {
+        Iter::new(self)
+    }
+
+    // range returns an iterator over the key/value pairs whose keys fall within range,
+    // for example `b.range(b"a"..b"c")`. Either end can be inclusive, exclusive or
+    // unbounded. Entries are read lazily and can be walked in reverse.
+    pub fn range<R: KeyRange>(&self, range: R) -> Iter<'a> {
+        Iter::range(self, &range)
+    }
+
+    // scan_prefix returns an iterator over the key/value pairs whose keys start with
+    // prefix. It can be walked forward or in reverse.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Iter<'a> {
+        Iter::prefix(self, prefix)
     }
 
     // Bucket retrieves a nested bucket by name.
     // Returns nil if the bucket does not exist.
     // The bucket instance is only valid for the lifetime of the transaction.
-    pub fn bucket(&mut self, name: &'static str) -> Option<Rc<RefCell<Bucket<'a>>>> {
-        match self.buckets.get(name) {
+    pub fn bucket(&self, name: &[u8]) -> Option<Rc<Bucket<'a>>> {
+        match self.buckets.borrow().get(name) {
             Some(ref b) => return Some(Rc::clone(b)),
             None => (),
         }
@@ -153,7 +193,7 @@ impl<'a> Bucket<'a> {
             Some(value) => {
                 match self.open_bucket(value) {
                     Some(ref child) => {
-                        self.buckets.insert(name, Rc::clone(child));
+                        self.buckets.borrow_mut().insert(name.to_vec(), Rc::clone(child));
                         return Some(Rc::clone(child))
                     },
                     None => panic!("failed to create bucket"),
@@ -165,128 +205,600 @@ impl<'a> Bucket<'a> {
 
     // Helper method that re-interprets a sub-subcket value
     // from a parent into a bucket.
-    fn open_bucket(&mut self, value: &'a str) -> Option<Rc<RefCell<Bucket<'a>>>> {
-        unimplemented!();
+    fn open_bucket(&self, value: &'a [u8]) -> Option<Rc<Bucket<'a>>> {
+        if value.len() < BUCKET_HEADER_SIZE {
+            return None;
+        }
+
+        // The header is read unaligned and the inline page is packed, so both can
+        // be read directly from the value.
+        let header = unsafe { ptr::read_unaligned(value.as_ptr() as *const _Bucket) };
+        let child = Bucket::new_rc(header, self.tx());
+
+        // Save a reference to the inline page if the bucket is inline.
+        if header.root == 0 && value.len() > BUCKET_HEADER_SIZE {
+            let p = unsafe { &*(value[BUCKET_HEADER_SIZE..].as_ptr() as *const Page) };
+            child.page.set(Some(p));
+        }
+        Some(child)
     }
 
     // creates a new bucket at the given key and returns the new bucket.
     // Returns an error if the key already exists, if the bucket name is blank, or if
     // the bucket name is too long.
     // The bucket instances is only valid for the lifetime of the transaction.
-    pub fn create_bucket(&mut self, key: &'static str) -> Result<Rc<RefCell<Bucket>>, &'static str> {
-        unimplemented!();
+    pub fn create_bucket(&self, key: &[u8]) -> Result<Rc<Bucket<'a>>, Error> {
+        self.check_writable()?;
+        if key.len() == 0 {
+            return Err(Error::BucketNameRequired);
+        } else if key.len() > MAX_KEY_SIZE as usize {
+            return Err(Error::KeyTooLarge);
+        }
+
+        // Move cursor to correct position.
+        let c = self.cursor();
+        let (k, _, flags) = c.borrow().seek1(key);
+
+        // Return an error if there is an existing key.
+        if k == Some(key) {
+            if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+                return Err(Error::BucketExists);
+            }
+            return Err(Error::IncompatibleValue);
+        }
+
+        // Create empty, inline bucket.
+        let tx = self.tx();
+        let child = Bucket::new_rc(_Bucket::new(), tx);
+        let root_node = Rc::new(RefCell::new(Node::new(Rc::clone(&child))));
+        {
+            let mut n = root_node.borrow_mut();
+            n.weak_self = Rc::downgrade(&root_node);
+            n.is_leaf = true;
+        }
+        *child.root_node.borrow_mut() = Some(root_node);
+
+        // Insert into node and cache the new bucket.
+        let key = tx.copy_bytes(key);
+        let value = child.write();
+        c.borrow().node().borrow_mut().put(key, key, Some(value), 0, BUCKET_LEAF_FLAG as u32);
+        self.buckets.borrow_mut().insert(key.to_vec(), Rc::clone(&child));
+
+        // Since subbuckets are not allowed on inline buckets, we need to
+        // dereference the inline page, if it exists. This will cause the bucket
+        // to be treated as a regular, non-inline bucket for the rest of the tx.
+        self.page.set(None);
+
+        Ok(child)
     }
 
     // creates a new bucket if it doesn't already exists and returns a reference to it.
     // Returns an error if the bucket name is blank, or if the bucket name is too long.
     // The bucket instance is only valid for the lifetime of the transaction.
-    pub fn create_bucket_if_not_exists(&mut self, key: &'static str) -> Result<Rc<RefCell<Bucket>>, &'static str> {
-        unimplemented!();
+    pub fn create_bucket_if_not_exists(&self, key: &[u8]) -> Result<Rc<Bucket<'a>>, Error> {
+        match self.create_bucket(key) {
+            Err(Error::BucketExists) => Ok(self.bucket(key).unwrap()),
+            r => r,
+        }
     }
 
     // deletes a bucket at the given kehy.
     // Returns an error if the bucket does not exists, or if the key represents a non-bucket value.
-    pub fn delete_bucket(&mut self, key: &'static str) -> Result<(), &'static str> {
-        unimplemented!();
+    pub fn delete_bucket(&self, key: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+
+        // Move cursor to correct position.
+        let c = self.cursor();
+        let (k, _, flags) = c.borrow().seek1(key);
+
+        // Return an error if bucket doesn't exist or is not a bucket.
+        if k != Some(key) {
+            return Err(Error::BucketNotFound);
+        } else if (flags & BUCKET_LEAF_FLAG as u32) == 0 {
+            return Err(Error::IncompatibleValue);
+        }
+
+        // Recursively delete all child buckets. The names are collected first
+        // so the child isn't modified while it is being iterated.
+        let child = self.bucket(key).unwrap();
+        let names: Vec<&'a [u8]> = child.iter().filter(|&(_, v)| v.is_none()).map(|(k, _)| k).collect();
+        for name in names {
+            child.delete_bucket(name)?;
+        }
+
+        // Remove cached copy.
+        self.buckets.borrow_mut().remove(key);
+
+        // Release all bucket pages to freelist.
+        child.nodes.borrow_mut().clear();
+        *child.root_node.borrow_mut() = None;
+        child.free();
+
+        // Delete the node if we have a matching key.
+        c.borrow().node().borrow_mut().del(key);
+        Ok(())
     }
 
     // returns the value for a key in the bucket.
     // Returns a nil value if the key does not exist or if the key is a nested bucket.
     // The returned value is only valid for the life of the transaction.
-    pub fn get(&self, key: &'static str) -> Option<&'static str> {
-        unimplemented!();
+    pub fn get(&self, key: &[u8]) -> Option<&'a [u8]> {
+        let (k, v, flags) = self.cursor().borrow().seek1(key);
+
+        // Return nil if this is a bucket.
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return None;
+        }
+
+        // If our target node isn't the same key as what's passed in then return nil.
+        if k != Some(key) {
+            return None;
+        }
+        v
     }
 
     // Put sets the value for a key in the bucket.
     // If the key exist then its previous value will be overwritten.
-    // Supplied value must remain valid for the life of the transaction.
+    // The key and value are copied into the transaction so they only need to be
+    // valid for the duration of the call.
     // Returns an error if the bucket was created from a read-only transaction, if the key is blank,
     // if the key is too large, or if the value is too large.
-    pub fn put(&mut self, key: &'static str, value: Option<&'static str>) -> Result<(), &'static str> {
-        unimplemented!();
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+        if key.len() == 0 {
+            return Err(Error::KeyRequired);
+        } else if key.len() > MAX_KEY_SIZE as usize {
+            return Err(Error::KeyTooLarge);
+        } else if value.len() as u64 > MAX_VALUE_SIZE as u64 {
+            return Err(Error::ValueTooLarge);
+        }
+
+        // Move cursor to correct position.
+        let c = self.cursor();
+        let (k, _, flags) = c.borrow().seek1(key);
+
+        // Return an error if there is an existing key with a bucket value.
+        if k == Some(key) && (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return Err(Error::IncompatibleValue);
+        }
+
+        // Insert into node.
+        let tx = self.tx();
+        let key = tx.copy_bytes(key);
+        let value = tx.copy_bytes(value);
+        c.borrow().node().borrow_mut().put(key, key, Some(value), 0, 0);
+        Ok(())
     }
 
     // Delete removes a key from the bucket.
     // If the key dose not exist then nothing is done and a nil error is returned.
     // Returns an error if the bucket was created from a read-only transaction.
-    pub fn delete(&mut self, key: &'static str) -> Result<(), &'static str> {
-        unimplemented!();
+    pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+
+        // Move cursor to correct position.
+        let c = self.cursor();
+        let (_, _, flags) = c.borrow().seek1(key);
+
+        // Return an error if there is already existing bucket value.
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return Err(Error::IncompatibleValue);
+        }
+
+        // Delete the node if we have a matching key.
+        c.borrow().node().borrow_mut().del(key);
+        Ok(())
     }
 
     // sequence returns the current integer for the bucket without incrementing it.
     pub fn sequence(&self) -> u64 {
-        unimplemented!();
+        self.bucket.borrow().sequence
     }
 
     // updates the sequence number for the bucket.
-    pub fn set_sequence(&mut self, v: u64) -> Result<(), &'static str> {
-        unimplemented!();
+    pub fn set_sequence(&self, v: u64) -> Result<(), Error> {
+        self.check_writable()?;
+
+        // Materialize the root node if it hasn't been already so that the
+        // bucket will be saved during commit.
+        self.materialize_root();
+
+        // Set the sequence.
+        self.bucket.borrow_mut().sequence = v;
+        Ok(())
     }
 
     // returns an autoincrementing integer for the bucket
-    pub fn next_sequence(&mut self) -> Result<u64, &'static str> {
-        unimplemented!();
+    pub fn next_sequence(&self) -> Result<u64, Error> {
+        self.check_writable()?;
+
+        // Materialize the root node if it hasn't been already so that the
+        // bucket will be saved during commit.
+        self.materialize_root();
+
+        // Increment and return the sequence.
+        let mut bucket = self.bucket.borrow_mut();
+        bucket.sequence += 1;
+        Ok(bucket.sequence)
+    }
+
+    // materialize_root loads the root node into the node cache if it isn't already.
+    fn materialize_root(&self) {
+        if self.root_node.borrow().is_none() {
+            self.node(self.root(), None);
+        }
     }
 
     // executes a function for each key/value pair in a bucket.
     // If the provided function returns an error then the iteration is stopped and
     // the error is returned to the caller. The provided function must not modify
     // the bucket; this will result in undefined behaviour.
-    pub fn for_each<F>(&self, f: F) -> Result<(), &'static str>
-    where F: Fn(&'static str, Option<&'static str>) -> Result<(), &'static str> {
-        unimplemented!();
+    pub fn for_each<F>(&self, f: F) -> Result<(), Error>
+    where F: Fn(&'a [u8], Option<&'a [u8]>) -> Result<(), Error> {
+        if self.tx().db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        }
+        let c = self.cursor();
+        let c = c.borrow();
+        let (mut k, mut v) = c.first();
+        while let Some(key) = k {
+            f(key, v)?;
+            let next = c.next();
+            k = next.0;
+            v = next.1;
+        }
+        Ok(())
     }
 
-    pub fn stats(&self) ->  BucketStats {
-        unimplemented!();
+    // stats retrieves stats on a bucket and all of its sub-buckets.
+    // Only pages that have been written are counted; changes still held in
+    // nodes by a writable transaction are not included.
+    pub fn stats(&self) -> BucketStats {
+        let mut s = BucketStats::new();
+        let mut sub_stats = BucketStats::new();
+        let page_size = self.tx().get_page_size() as i64;
+        let inline = self.root() == 0;
+        s.bucket_n += 1;
+        if inline {
+            s.inline_bucket_n += 1;
+        }
+        self.forEachPage(|p, depth| {
+            if (p.flags & LEAF_PAGE_FLAG) != 0 {
+                s.key_n += p.count as i64;
+
+                // used totals the used bytes for the page
+                let mut used = page::get_page_header_size() as i64;
+                if p.count != 0 {
+                    // If page has any elements, add all element headers.
+                    used += (page::LEAF_PAGE_ELEMENT_SIZE * (p.count as usize - 1)) as i64;
+
+                    // Add all element key, value sizes.
+                    // The computation takes advantage of the fact that the position
+                    // of the last element's key/value equals to the total of the sizes
+                    // of all previous elements' keys and values.
+                    // It also includes the last element's header.
+                    let last = unsafe { &*p.leaf_page_element(p.count - 1) };
+                    used += (last.pos + last.ksize + last.vsize) as i64;
+                }
+
+                if inline {
+                    // For inlined bucket just update the inline stats
+                    s.inline_bucket_inuse += used;
+                } else {
+                    // For non-inlined bucket update all the leaf stats
+                    s.leaf_page_n += 1;
+                    s.leaf_inuse += used;
+                    s.leaf_overflow_n += p.overflow as i64;
+
+                    // Collect stats from sub-buckets.
+                    // Do that by iterating over all element headers
+                    // looking for the ones with the bucket leaf flag.
+                    for i in 0..p.count {
+                        let e = unsafe { &*p.leaf_page_element(i) };
+                        if (e.flags & BUCKET_LEAF_FLAG as u32) != 0 {
+                            // For any bucket element, open the element value
+                            // and recursively call stats on the contained bucket.
+                            if let Some(child) = self.open_bucket(e.value()) {
+                                sub_stats.add(&child.stats());
+                            }
+                        }
+                    }
+                }
+            } else if (p.flags & BRANCH_PAGE_FLAG) != 0 {
+                s.branch_page_n += 1;
+                let last = unsafe { &*p.branch_page_element(p.count - 1) };
+
+                // used totals the used bytes for the page
+                // Add header and all element headers.
+                let mut used = (page::get_page_header_size() + page::BRANCH_PAGE_ELEMENT_SIZE * (p.count as usize - 1)) as i64;
+
+                // Add size of all keys and values.
+                // Again, use the fact that last element's position equals to
+                // the total of key, value sizes of all previous elements.
+                used += (last.pos + last.ksize) as i64;
+                s.branch_inuse += used;
+                s.branch_overflow_n += p.overflow as i64;
+            }
+
+            // Keep track of maximum page depth.
+            if depth + 1 > s.depth {
+                s.depth = depth + 1;
+            }
+        });
+
+        // Alloc stats can be computed from page counts and page size.
+        s.branch_alloc = (s.branch_page_n + s.branch_overflow_n) * page_size;
+        s.leaf_alloc = (s.leaf_page_n + s.leaf_overflow_n) * page_size;
+
+        // Add the max depth of sub-buckets to get total nested depth.
+        s.depth += sub_stats.depth;
+        // Add the stats for all sub-buckets
+        s.add(&sub_stats);
+        s
     }
 
     // forEachPage iterates over every page in a bucket, including inline pages.
-    pub fn forEachPage<F>(&self, f: F) -> (Option<Rc<RefCell<Page>>>, i64)
-    where F: Fn(Option<Rc<RefCell<Page>>>, Option<Rc<RefCell<Node>>>, i64) {
-        unimplemented!();
+    pub fn forEachPage<F>(&self, mut f: F)
+    where F: FnMut(&'a Page, i64) {
+        // If we have an inline page then just use that.
+        if let Some(p) = self.page.get() {
+            f(p, 0);
+            return;
+        }
+
+        // Otherwise traverse the page hierarchy.
+        self.tx().for_each_page(self.root(), 0, &mut f);
     }
 
     // spill writes all the nodes for this bucket to dirty pages.
-    fn spill(&mut self) -> Result<(), &'static str> {
-        unimplemented!();
+    pub(crate) fn spill(&self) -> Result<(), Error> {
+        // Spill all the child buckets first.
+        let children: Vec<(Vec<u8>, Rc<Bucket<'a>>)> = self.buckets.borrow().iter()
+            .map(|(name, child)| (name.clone(), Rc::clone(child)))
+            .collect();
+        for (name, child) in children {
+            // Small buckets are written inline in the value. Anything larger gets
+            // pages of its own and only its header is stored in the value.
+            let value = if child.inlineable() {
+                child.free();
+                child.write()
+            } else {
+                child.spill()?;
+                child.header_bytes()
+            };
+
+            // Skip writing the bucket if there are no materialized nodes.
+            if child.root_node.borrow().is_none() {
+                continue;
+            }
+
+            // Update the child bucket header in this bucket.
+            let c = self.cursor();
+            let (k, _, flags) = c.borrow().seek1(&name);
+            if k != Some(&name[..]) {
+                panic!("misplaced bucket header: {:?} -> {:?}", name, k);
+            }
+            if (flags & BUCKET_LEAF_FLAG as u32) == 0 {
+                panic!("unexpected bucket header flag: {:x}", flags);
+            }
+            let key = self.tx().copy_bytes(&name);
+            c.borrow().node().borrow_mut().put(key, key, Some(value), 0, BUCKET_LEAF_FLAG as u32);
+        }
+
+        // Ignore if there's not a materialized root node.
+        let root_node = match *self.root_node.borrow() {
+            None => return Ok(()),
+            Some(ref n) => Rc::clone(n),
+        };
+
+        // Spill nodes. Parents created by splitting the root are only weakly
+        // referenced by their children so they're held here until the new root is set.
+        let _new_parents = Node::spill(&root_node)?;
+        let root_node = root_node.borrow().root();
+
+        // Update the root node for this bucket.
+        let pgid = root_node.borrow().pgid;
+        let meta_pgid = self.tx().meta.borrow().pgid;
+        if pgid >= meta_pgid {
+            panic!("pgid ({}) above high water mark ({})", pgid, meta_pgid);
+        }
+        self.bucket.borrow_mut().root = pgid;
+        *self.root_node.borrow_mut() = Some(root_node);
+
+        Ok(())
     }
 
     // returns true if a bucket is small enough to be written inline and if it contains no subbuckets.
     // Otherwise returns false.
     fn inlineable(&self) -> bool {
-        unimplemented!();
+        let root_node = self.root_node.borrow();
+        let n = match *root_node {
+            Some(ref n) => n.borrow(),
+            None => return false,
+        };
+
+        // Bucket must only contain a single leaf node.
+        if !n.is_leaf {
+            return false;
+        }
+
+        // Bucket is not inlineable if it contains subbuckets or if it goes beyond
+        // our threshold for inline bucket size.
+        let mut size = page::get_page_header_size();
+        for inode in &n.inodes {
+            size += page::LEAF_PAGE_ELEMENT_SIZE + inode.key.len() + inode.value.map_or(0, |v| v.len());
+            if (inode.flags & BUCKET_LEAF_FLAG as u32) != 0 {
+                return false;
+            } else if size > self.max_inline_bucket_size() {
+                return false;
+            }
+        }
+        true
     }
 
     // returns the maximum total size of a bucket to make it a candidate for inlining.
-    fn max_inline_bucket_size(&self) -> i64 {
-        unimplemented!();
+    fn max_inline_bucket_size(&self) -> usize {
+        self.tx().get_page_size() / 4
     }
 
     // write allocates and writes a bucket to a byte slice.
-    fn write(&self) -> &'static str  {
-        unimplemented!();
+    fn write(&self) -> &'a [u8] {
+        let root_node = self.root_node.borrow();
+        let mut n = root_node.as_ref().unwrap().borrow_mut();
+
+        // Allocate the appropriate size.
+        let mut value = vec![0u8; BUCKET_HEADER_SIZE + n.size()];
+
+        // Write a bucket header.
+        unsafe { ptr::write_unaligned(value.as_mut_ptr() as *mut _Bucket, *self.bucket.borrow()) };
+
+        // Convert byte slice to a fake page and write the root node.
+        {
+            let p = unsafe { &mut *(value[BUCKET_HEADER_SIZE..].as_mut_ptr() as *mut Page) };
+            n.write(p);
+        }
+        self.tx().copy_bytes(&value)
     }
 
     // attempts to balance all nodes.
-    fn rebalance(&mut self) {
-        unimplemented!();
+    pub(crate) fn rebalance(&self) {
+        let nodes: Vec<Rc<RefCell<Node<'a>>>> = self.nodes.borrow().values().cloned().collect();
+        for n in nodes {
+            // Skip nodes that were merged away while rebalancing an earlier node.
+            let cached = match self.nodes.borrow().get(&n.borrow().pgid) {
+                Some(m) => Rc::ptr_eq(m, &n),
+                None => false,
+            };
+            if cached {
+                Node::reblance(&n);
+            }
+        }
+        let children: Vec<Rc<Bucket<'a>>> = self.buckets.borrow().values().cloned().collect();
+        for child in children {
+            child.rebalance();
+        }
+    }
+
+    // clear drops the node and sub-bucket caches. Nodes hold a reference back to
+    // their bucket so they have to be released explicitly when the transaction closes.
+    pub(crate) fn clear(&self) {
+        self.nodes.borrow_mut().clear();
+        *self.root_node.borrow_mut() = None;
+        for (_, child) in self.buckets.borrow_mut().drain() {
+            child.clear();
+        }
     }
 
     // free recursively frees all pages in the bucket.
-    fn free(&mut self) {
-        unimplemented!();
+    fn free(&self) {
+        if self.root() == 0 {
+            return;
+        }
+
+        let tx = self.tx();
+        self.for_each_page_node(|p, n, _| {
+            match p {
+                Some(p) => tx.free(p.id),
+                None => n.unwrap().borrow_mut().free(),
+            }
+        });
+        self.bucket.borrow_mut().root = 0;
+    }
+
+    // for_each_page_node iterates over every page (or node) in a bucket.
+    // This also includes inline pages.
+    fn for_each_page_node<F>(&self, mut f: F)
+    where F: FnMut(Option<&'a Page>, Option<&Rc<RefCell<Node<'a>>>>, i64) {
+        // If we have an inline page or root node then just use that.
+        if let Some(p) = self.page.get() {
+            f(Some(p), None, 0);
+            return;
+        }
+        self._for_each_page_node(self.root(), 0, &mut f);
+    }
+
+    fn _for_each_page_node<F>(&self, pgid: pgid_t, depth: i64, f: &mut F)
+    where F: FnMut(Option<&'a Page>, Option<&Rc<RefCell<Node<'a>>>>, i64) {
+        let (p, n) = self.page_node(pgid);
+
+        // Execute function and recursively iterate over children.
+        let children: Vec<pgid_t> = match (p, n) {
+            (Some(p), _) => {
+                f(Some(p), None, depth);
+                if (p.flags & BRANCH_PAGE_FLAG) != 0 {
+                    (0..p.count).map(|i| unsafe { (*p.branch_page_element(i)).pgid }).collect()
+                } else {
+                    vec![]
+                }
+            },
+            (None, Some(n)) => {
+                f(None, Some(&n), depth);
+                let n = n.borrow();
+                if !n.is_leaf {
+                    n.inodes.iter().map(|inode| inode.pgid).collect()
+                } else {
+                    vec![]
+                }
+            },
+            (None, None) => vec![],
+        };
+        for pgid in children {
+            self._for_each_page_node(pgid, depth + 1, f);
+        }
+    }
+
+    // header_bytes returns a copy of the bucket header, owned by the transaction,
+    // to be stored as the bucket's value in its parent.
+    fn header_bytes(&self) -> &'a [u8] {
+        let header = *self.bucket.borrow();
+        let bytes = unsafe { slice::from_raw_parts(&header as *const _Bucket as *const u8, BUCKET_HEADER_SIZE) };
+        self.tx().copy_bytes(bytes)
     }
 
     // dereference removes all references to the old mmap.
-    fn dereference(&mut self) {
-        unimplemented!();
+    pub(crate) fn dereference(&self) {
+        // An inline bucket that hasn't been read into a node still points at its
+        // parent's value so it's materialized to be copied like any other node.
+        if self.page.get().is_some() {
+            self.materialize_root();
+        }
+
+        let root_node = match *self.root_node.borrow() {
+            Some(ref n) => Some(n.borrow().root()),
+            None => None,
+        };
+        if let Some(n) = root_node {
+            n.borrow_mut().dereference();
+        }
+
+        let children: Vec<Rc<Bucket<'a>>> = self.buckets.borrow().values().cloned().collect();
+        for child in children {
+            child.dereference();
+        }
     }
 
     // page_node returns the in-memory node, if it exists.
     // Otherwise returns the underlying page.
-    fn page_node(&mut self, id: pgid_t) -> (Rc<RefCell<Page>>, Rc<RefCell<Node>>) {
-        unimplemented!();
+    pub(crate) fn page_node(&self, id: pgid_t) -> (Option<&'a Page>, Option<Rc<RefCell<Node<'a>>>>) {
+        // Inline buckets have a fake page embedded in their value so treat them
+        // differently. We'll return the root_node (if available) or the fake page.
+        if self.root() == 0 {
+            if id != 0 {
+                panic!("inline bucket non-zero page access(2): {} != 0", id);
+            }
+            if let Some(ref n) = *self.root_node.borrow() {
+                return (None, Some(Rc::clone(n)));
+            }
+            return (self.page.get(), None);
+        }
+
+        // Check the node cache for non-inline buckets.
+        if let Some(n) = self.nodes.borrow().get(&id) {
+            return (None, Some(Rc::clone(n)));
+        }
+
+        // Finally lookup the page from the transaction if no node is materialized.
+        (Some(self.tx().page(id)), None)
     }
 }
 
@@ -294,6 +806,8 @@ impl<'a> Bucket<'a> {
 // This is stored as the "value" of a bucket key. If the _Bucket is small enough,
 // then its root page can be stored inline in the "value", after the _Bucket
 // header. In the case of inline buckets, the "root" will be 0.
+#[derive(Clone, Copy)]
+#[repr(C)]
 pub struct _Bucket {
     pub root: pgid_t,  // page id of the _Bucket's root-level page
     pub sequence: u64, // monotonically incrementing, used by NextSequence()
@@ -308,6 +822,8 @@ impl _Bucket {
     }
 }
 
+// BucketStats records statistics about resources used by a bucket.
+#[derive(Debug)]
 pub struct BucketStats {
     // Page count statistics.
     pub branch_page_n: i64,       // number of logical branch pages
@@ -330,3 +846,639 @@ pub struct BucketStats {
     pub inline_bucket_n: i64,     // total number on inlined buckets
     pub inline_bucket_inuse: i64, // bytes used for inlined buckets (also accounted for in LeafInuse)
 }
+
+impl BucketStats {
+    pub fn new() -> BucketStats {
+        BucketStats {
+            branch_page_n: 0,
+            branch_overflow_n: 0,
+            leaf_page_n: 0,
+            leaf_overflow_n: 0,
+            key_n: 0,
+            depth: 0,
+            branch_alloc: 0,
+            branch_inuse: 0,
+            leaf_alloc: 0,
+            leaf_inuse: 0,
+            bucket_n: 0,
+            inline_bucket_n: 0,
+            inline_bucket_inuse: 0,
+        }
+    }
+
+    // add adds the stats of another bucket. The depth is the deeper of the two.
+    pub fn add(&mut self, other: &BucketStats) {
+        self.branch_page_n += other.branch_page_n;
+        self.branch_overflow_n += other.branch_overflow_n;
+        self.leaf_page_n += other.leaf_page_n;
+        self.leaf_overflow_n += other.leaf_overflow_n;
+        self.key_n += other.key_n;
+        if self.depth < other.depth {
+            self.depth = other.depth;
+        }
+        self.branch_alloc += other.branch_alloc;
+        self.branch_inuse += other.branch_inuse;
+        self.leaf_alloc += other.leaf_alloc;
+        self.leaf_inuse += other.leaf_inuse;
+        self.bucket_n += other.bucket_n;
+        self.inline_bucket_n += other.inline_bucket_n;
+        self.inline_bucket_inuse += other.inline_bucket_inuse;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_KEY_SIZE;
+    use errors::Error;
+    use page::{BRANCH_PAGE_FLAG, LEAF_PAGE_FLAG};
+    use std::cell::Cell;
+    use std::time::Duration;
+    use db::DB;
+    use db::tests::TestDB;
+    use tx::Tx;
+
+    #[test]
+    fn bucket_put_get() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            b.put(b"foo", b"bar")?;
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(b.get(b"fo"), None);
+            assert_eq!(b.get(b"fooo"), None);
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            assert_eq!(tx.root_bucket().get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_put_copies_data() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            {
+                let mut key = String::from("foo");
+                let mut value = vec![1, 2, 3];
+                b.put(key.as_bytes(), &value)?;
+
+                // Changing the caller's buffers doesn't affect the stored data.
+                key.push('x');
+                value[0] = 42;
+            }
+            assert_eq!(b.get(b"foo"), Some(&[1, 2, 3][..]));
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            assert_eq!(tx.root_bucket().get(b"foo"), Some(&[1, 2, 3][..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_put_binary() {
+        let db = TestDB::new();
+        static KEYS: [[u8; 8]; 3] = [
+            [0, 0, 0, 0, 0, 0, 1, 0],
+            [0, 0, 0, 0, 0, 0, 0, 255],
+            [0xff, 0xfe, 0, 0, 0, 0, 0, 0],
+        ];
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for k in KEYS.iter() {
+                b.put(k, &[0xc3, 0x28])?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            let c = tx.root_bucket().cursor();
+            let c = c.borrow();
+            assert_eq!(c.first(), (Some(&KEYS[1][..]), Some(&[0xc3, 0x28][..])));
+            assert_eq!(c.next().0, Some(&KEYS[0][..]));
+            assert_eq!(c.next().0, Some(&KEYS[2][..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_put_read_only() {
+        let db = TestDB::new();
+        DB::view(&db, |tx| {
+            assert_eq!(tx.root_bucket().put(b"foo", b"bar"), Err(Error::TxNotWritable));
+            assert_eq!(tx.root_bucket().delete(b"foo"), Err(Error::TxNotWritable));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_closed_tx() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| tx.root_bucket().put(b"foo", b"bar")).unwrap();
+
+        let tx = DB::begin(&db, false).unwrap();
+        let root = tx.root_bucket();
+        let c = root.cursor();
+        assert_eq!(root.get(b"foo"), Some(&b"bar"[..]));
+        Tx::rollback(&tx).unwrap();
+
+        // Reads find nothing and writes fail once the transaction is closed.
+        assert_eq!(root.get(b"foo"), None);
+        assert!(root.bucket(b"foo").is_none());
+        assert_eq!(c.borrow().first(), (None, None));
+        assert_eq!(c.borrow().next(), (None, None));
+        assert_eq!(c.borrow().seek(b"foo"), (None, None));
+        assert_eq!(root.iter().count(), 0);
+        assert_eq!(root.put(b"foo", b"baz"), Err(Error::TxClosed));
+        assert_eq!(c.borrow_mut().delete(), Err(Error::TxClosed));
+    }
+
+    #[test]
+    fn bucket_put_invalid_key() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            assert_eq!(b.put(b"", b"bar"), Err(Error::KeyRequired));
+            assert_eq!(b.put(&vec![0; MAX_KEY_SIZE as usize + 1], b"bar"), Err(Error::KeyTooLarge));
+
+            // A key of exactly the maximum size is still allowed.
+            let key = vec![1; MAX_KEY_SIZE as usize];
+            b.put(&key, b"bar")?;
+            assert_eq!(b.get(&key), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_delete() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            b.put(b"foo", b"bar")?;
+            b.put(b"baz", b"bat")?;
+            b.delete(b"foo")?;
+            b.delete(b"missing")?;
+            assert_eq!(b.get(b"foo"), None);
+            assert_eq!(b.get(b"baz"), Some(&b"bat"[..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_for_each() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            b.put(b"foo", b"0000")?;
+            b.put(b"baz", b"0001")?;
+            b.put(b"bar", b"0002")?;
+
+            let seen = ::std::cell::RefCell::new(vec![]);
+            b.for_each(|k, v| {
+                seen.borrow_mut().push((k, v.unwrap()));
+                Ok(())
+            })?;
+            assert_eq!(*seen.borrow(), vec![
+                (&b"bar"[..], &b"0002"[..]),
+                (&b"baz"[..], &b"0001"[..]),
+                (&b"foo"[..], &b"0000"[..]),
+            ]);
+            assert_eq!(b.for_each(|_, _| Err(Error::BucketExists)), Err(Error::BucketExists));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_create_bucket() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().create_bucket(b"widgets")?;
+            b.put(b"foo", b"bar")?;
+            assert_eq!(tx.root_bucket().bucket(b"widgets").unwrap().get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let root = tx.root_bucket();
+            let b = root.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+
+            // Buckets are listed with a nil value and can't be read as values.
+            assert_eq!(root.get(b"widgets"), None);
+            assert_eq!(root.iter().next(), Some((&b"widgets"[..], None)));
+            assert!(root.bucket(b"widget").is_none());
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_create_bucket_errors() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            root.create_bucket(b"widgets")?;
+            root.put(b"foo", b"bar")?;
+
+            assert_eq!(root.create_bucket(b"widgets").err(), Some(Error::BucketExists));
+            assert_eq!(root.create_bucket(b"").err(), Some(Error::BucketNameRequired));
+            assert_eq!(root.create_bucket(&vec![0; MAX_KEY_SIZE as usize + 1]).err(), Some(Error::KeyTooLarge));
+            assert_eq!(root.create_bucket(b"foo").err(), Some(Error::IncompatibleValue));
+            assert_eq!(root.put(b"widgets", b"bar").err(), Some(Error::IncompatibleValue));
+            assert_eq!(root.delete(b"widgets").err(), Some(Error::IncompatibleValue));
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            assert_eq!(tx.root_bucket().create_bucket(b"bar").err(), Some(Error::TxNotWritable));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_create_bucket_if_not_exists() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            root.create_bucket_if_not_exists(b"widgets")?.put(b"foo", b"bar")?;
+            assert_eq!(root.create_bucket_if_not_exists(b"widgets")?.get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().create_bucket_if_not_exists(b"widgets")?;
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(tx.root_bucket().create_bucket_if_not_exists(b"").err(), Some(Error::BucketNameRequired));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_nested() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let widgets = tx.root_bucket().create_bucket(b"widgets")?;
+            widgets.create_bucket(b"foo")?.put(b"baz", b"bat")?;
+            widgets.put(b"bar", b"0000")?;
+            Ok(())
+        }).unwrap();
+
+        // Update the nested bucket in a later transaction without touching its parent directly.
+        DB::update(&db, |tx| {
+            let foo = tx.root_bucket().bucket(b"widgets").unwrap().bucket(b"foo").unwrap();
+            foo.put(b"baz", b"yyyy")?;
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let widgets = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert_eq!(widgets.get(b"bar"), Some(&b"0000"[..]));
+            assert_eq!(widgets.bucket(b"foo").unwrap().get(b"baz"), Some(&b"yyyy"[..]));
+            assert!(widgets.bucket(b"bar").is_none());
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_delete_bucket() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let widgets = tx.root_bucket().create_bucket(b"widgets")?;
+            for i in 0..500u32 {
+                widgets.put(format!("{:04}", i).as_bytes(), &[0; 100])?;
+            }
+            widgets.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            Ok(())
+        }).unwrap();
+
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            root.delete_bucket(b"widgets")?;
+            assert!(root.bucket(b"widgets").is_none());
+
+            // Every page of the bucket and its nested bucket is freed.
+            let txid = tx.meta.borrow().txid;
+            assert!(db.freelist.lock().unwrap().pending[&txid].len() > 20);
+
+            assert_eq!(root.delete_bucket(b"widgets").err(), Some(Error::BucketNotFound));
+            root.put(b"foo", b"bar")?;
+            assert_eq!(root.delete_bucket(b"foo").err(), Some(Error::IncompatibleValue));
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            assert!(tx.root_bucket().bucket(b"widgets").is_none());
+            Ok(())
+        }).unwrap();
+
+        // The name can be reused for a new, empty bucket.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().create_bucket(b"widgets")?;
+            assert_eq!(b.get(b"0000"), None);
+            assert!(b.bucket(b"sub").is_none());
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_inline() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            for i in 0..1000u32 {
+                root.create_bucket(format!("{:04}", i).as_bytes())?.put(b"foo", b"bar")?;
+            }
+            Ok(())
+        }).unwrap();
+
+        // Small buckets are stored in their parent's leaf instead of taking a page each.
+        DB::view(&db, |tx| {
+            assert!(tx.meta.borrow().pgid < 100);
+            let b = tx.root_bucket().bucket(b"0500").unwrap();
+            assert_eq!(b.root(), 0);
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(b.iter().count(), 1);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_inline_promote_demote() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            tx.root_bucket().create_bucket(b"widgets")?.put(b"foo", b"bar")?;
+            Ok(())
+        }).unwrap();
+
+        // Growing past a quarter page moves the bucket onto its own page.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert_eq!(b.root(), 0);
+            for i in 0..20u32 {
+                b.put(format!("{:02}", i).as_bytes(), &[0; 100])?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert!(b.root() != 0);
+            assert_eq!(b.iter().count(), 21);
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+
+        // Shrinking below the threshold frees the page and inlines it again.
+        let pgid = Cell::new(0);
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            pgid.set(b.root());
+            for i in 0..20u32 {
+                b.delete(format!("{:02}", i).as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+        assert!(db.freelist.lock().unwrap().pending.values().any(|ids| ids.contains(&pgid.get())));
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert_eq!(b.root(), 0);
+            assert_eq!(b.iter().collect::<Vec<_>>(), vec![(&b"foo"[..], Some(&b"bar"[..]))]);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_inline_with_subbucket() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            tx.root_bucket().create_bucket(b"widgets")?.put(b"foo", b"bar")?;
+            Ok(())
+        }).unwrap();
+
+        // An inline bucket with a nested bucket is no longer inlined.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            b.create_bucket(b"sub")?.put(b"baz", b"bat")?;
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert!(b.root() != 0);
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            let sub = b.bucket(b"sub").unwrap();
+            assert_eq!(sub.root(), 0);
+            assert_eq!(sub.get(b"baz"), Some(&b"bat"[..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_sequence() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            let b = root.create_bucket(b"widgets")?;
+            assert_eq!(b.sequence(), 0);
+            assert_eq!(b.next_sequence()?, 1);
+            assert_eq!(b.next_sequence()?, 2);
+            root.create_bucket(b"woojits")?.set_sequence(1000)?;
+            root.set_sequence(42)?;
+            Ok(())
+        }).unwrap();
+
+        // Only the sequence changes so the bucket headers must still be rewritten.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert_eq!(b.next_sequence()?, 3);
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let root = tx.root_bucket();
+            assert_eq!(root.sequence(), 42);
+            assert_eq!(root.bucket(b"widgets").unwrap().sequence(), 3);
+            assert_eq!(root.bucket(b"woojits").unwrap().sequence(), 1000);
+            assert_eq!(root.next_sequence().err(), Some(Error::TxNotWritable));
+            assert_eq!(root.set_sequence(1).err(), Some(Error::TxNotWritable));
+            Ok(())
+        }).unwrap();
+
+        // Rolled back increments are discarded.
+        let tx = DB::begin(&db, true).unwrap();
+        assert_eq!(tx.root_bucket().bucket(b"widgets").unwrap().next_sequence(), Ok(4));
+        tx.rollback().unwrap();
+        DB::view(&db, |tx| {
+            assert_eq!(tx.root_bucket().bucket(b"widgets").unwrap().sequence(), 3);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_delete_rebalances() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..2000u32 {
+                b.put(format!("{:05}", i).as_bytes(), &[0; 100])?;
+            }
+            Ok(())
+        }).unwrap();
+        let leaves = Cell::new(0);
+        DB::view(&db, |tx| {
+            let p = tx.page(tx.root_bucket().root());
+            assert!(p.flags & BRANCH_PAGE_FLAG != 0);
+            leaves.set(p.count);
+            Ok(())
+        }).unwrap();
+
+        // Delete all but every tenth key. The sparse leaves are merged with their siblings.
+        let tx = DB::begin(&db, true).unwrap();
+        {
+            let b = tx.root_bucket();
+            for i in 0..2000u32 {
+                if i % 10 != 0 {
+                    b.delete(format!("{:05}", i).as_bytes()).unwrap();
+                }
+            }
+        }
+        Tx::commit(&tx).unwrap();
+        assert!(tx.stats.borrow().rebalance > 0);
+        assert!(tx.stats.borrow().rebalance_time > Duration::new(0, 0));
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            assert!(tx.page(b.root()).count < leaves.get() / 2);
+            let keys: Vec<Vec<u8>> = b.iter().map(|(k, _)| k.to_vec()).collect();
+            let expected: Vec<Vec<u8>> = (0..200u32).map(|i| format!("{:05}", i * 10).into_bytes()).collect();
+            assert_eq!(keys, expected);
+            Ok(())
+        }).unwrap();
+
+        // Emptied leaves are removed and the root collapses into the only one left.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 5..200u32 {
+                b.delete(format!("{:05}", i * 10).as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            assert!(tx.page(b.root()).flags & LEAF_PAGE_FLAG != 0);
+            assert_eq!(b.iter().count(), 5);
+            Ok(())
+        }).unwrap();
+
+        // Deleting everything leaves an empty leaf root.
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..5u32 {
+                b.delete(format!("{:05}", i * 10).as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            assert_eq!({ tx.page(b.root()).count }, 0);
+            assert_eq!(b.iter().next(), None);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_dereference_on_remap() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            for i in 0..500u32 {
+                root.put(format!("{:05}", i).as_bytes(), &[1; 100])?;
+            }
+            root.create_bucket(b"inline")?.put(b"foo", b"bar")?;
+            Ok(())
+        }).unwrap();
+
+        // Materialize nodes pointing into the mmap, then write enough to grow the
+        // file and move the mapping before those nodes are spilled.
+        let tx = DB::begin(&db, true).unwrap();
+        let datasz = db.datasz();
+        {
+            let root = tx.root_bucket();
+            for i in 0..500u32 {
+                if i % 50 == 0 {
+                    root.delete(format!("{:05}", i).as_bytes()).unwrap();
+                }
+            }
+            let inline = root.bucket(b"inline").unwrap();
+            for i in 500..5000u32 {
+                root.put(format!("{:05}", i).as_bytes(), &[2; 1000]).unwrap();
+            }
+            assert_eq!(inline.get(b"foo"), Some(&b"bar"[..]));
+        }
+        Tx::commit(&tx).unwrap();
+        assert!(db.datasz() > datasz);
+        assert!(tx.stats.borrow().node_deref > 0);
+
+        DB::view(&db, |tx| {
+            let root = tx.root_bucket();
+            assert_eq!(root.get(b"00000"), None);
+            assert_eq!(root.get(b"00001"), Some(&[1; 100][..]));
+            assert_eq!(root.get(b"00499"), Some(&[1; 100][..]));
+            assert_eq!(root.get(b"04999"), Some(&[2; 1000][..]));
+            assert_eq!(root.iter().count(), 4990 + 1);
+            assert_eq!(root.bucket(b"inline").unwrap().get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_stats() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            for i in 0..500u32 {
+                root.put(format!("{:05}", i).as_bytes(), &[0; 100])?;
+            }
+            let big = root.create_bucket(b"big")?;
+            for i in 0..1000u32 {
+                big.put(format!("{:05}", i).as_bytes(), &[0; 100])?;
+            }
+            root.create_bucket(b"small")?.put(b"foo", b"bar")?;
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let root = tx.root_bucket();
+            let page_size = tx.get_page_size() as i64;
+
+            let small = root.bucket(b"small").unwrap().stats();
+            assert_eq!(small.key_n, 1);
+            assert_eq!(small.depth, 1);
+            assert_eq!(small.bucket_n, 1);
+            assert_eq!(small.inline_bucket_n, 1);
+            assert_eq!(small.inline_bucket_inuse, 16 + 16 + 6);
+            assert_eq!(small.leaf_page_n, 0);
+            assert_eq!(small.leaf_alloc, 0);
+
+            let big = root.bucket(b"big").unwrap().stats();
+            assert_eq!(big.key_n, 1000);
+            assert_eq!(big.depth, 2);
+            assert_eq!(big.bucket_n, 1);
+            assert_eq!(big.inline_bucket_n, 0);
+            assert_eq!(big.branch_page_n, 1);
+            assert!(big.leaf_page_n > 1);
+            assert_eq!(big.leaf_alloc, (big.leaf_page_n + big.leaf_overflow_n) * page_size);
+            assert!(big.leaf_inuse > 1000 * 100 && big.leaf_inuse <= big.leaf_alloc);
+            assert!(big.branch_inuse > 0 && big.branch_inuse <= big.branch_alloc);
+
+            // The top bucket includes everything below it.
+            let s = root.stats();
+            assert_eq!(s.key_n, 500 + 2 + 1000 + 1);
+            assert_eq!(s.depth, 4);
+            assert_eq!(s.bucket_n, 3);
+            assert_eq!(s.inline_bucket_n, 1);
+            assert_eq!(s.inline_bucket_inuse, small.inline_bucket_inuse);
+            assert!(s.leaf_page_n > big.leaf_page_n);
+            assert_eq!(s.leaf_alloc, (s.leaf_page_n + s.leaf_overflow_n) * page_size);
+            assert_eq!(s.branch_alloc, (s.branch_page_n + s.branch_overflow_n) * page_size);
+            Ok(())
+        }).unwrap();
+    }
+}
@@ -1,10 +1,12 @@
 use bucket::Bucket;
-use page::{Page, LEAF_PAGE_FLAG};
+use page::{Page, BRANCH_PAGE_FLAG, LEAF_PAGE_FLAG, BUCKET_LEAF_FLAG};
 use node::Node;
 use types::pgid_t;
+use errors::Error;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::ops::{Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive};
 
 // Cursor represents an iterator that can traverse over all key/value pairs in a bucket in sorted order.
 // Cursors see nested buckets with value == nil.
@@ -16,114 +18,614 @@ use std::cell::RefCell;
 // and return unexpected keys and/or values. You must reposition your cursor
 // after mutating data.
 pub struct Cursor<'a> {
-    bucket: Rc<RefCell<Bucket<'a>>>,
-    stack: Vec<elem_ref<'a>>,
+    bucket: Rc<Bucket<'a>>,
+    stack: RefCell<Vec<elem_ref<'a>>>,
 }
 
 impl<'a> Cursor<'a> {
-    pub fn new(bucket: &Rc<RefCell<Bucket<'a>>>) -> Cursor<'a> {
+    pub(crate) fn new(bucket: &Rc<Bucket<'a>>) -> Cursor<'a> {
         Cursor {
             bucket: Rc::clone(bucket),
-            stack: vec![],
+            stack: RefCell::new(vec![]),
         }
     }
 
+    // closed returns true if the cursor's transaction has been closed.
+    // Pages from a closed transaction may no longer be mapped so nothing is read.
+    fn closed(&self) -> bool {
+        self.bucket.tx().db.borrow().is_none()
+    }
+
     // returns the bucket that this cursor was created from.
-    pub fn get_bucket(&self) -> &Rc<RefCell<Bucket<'a>>> {
+    pub fn get_bucket(&self) -> &Rc<Bucket<'a>> {
         &self.bucket
     }
 
     // First moves the cursor to the first item in the bucket and returns its key and value.
     // If the bucket is empty then a nil key and value are returned.
     // The returned key and value are only valid for the life of the transaction.
-    pub fn first(&self) -> (Option<&'a str>, Option<&'a str>) {
-        unimplemented!()
+    pub fn first(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>) {
+        if self.closed() {
+            return (None, None);
+        }
+        {
+            let mut stack = self.stack.borrow_mut();
+            stack.clear();
+            let (p, n) = self.bucket.page_node(self.bucket.root());
+            stack.push(elem_ref { page: p, node: n, index: 0 });
+        }
+        self._first();
+
+        // If we land on an empty page then move to the next value.
+        if self.stack.borrow().last().unwrap().count() == 0 {
+            self._next();
+        }
+
+        let (k, v, flags) = self.key_value();
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return (k, None);
+        }
+        (k, v)
     }
 
     // moves the cursor to the first leaf element under the last page in the bucket.
     fn _first(&self) {
-        unimplemented!()
+        loop {
+            // Exit when we hit a leaf page.
+            let pgid = {
+                let stack = self.stack.borrow();
+                let r = stack.last().unwrap();
+                if r.is_leaf() {
+                    break;
+                }
+                r.pgid()
+            };
+
+            // Keep adding pages pointing to the first element to the stack.
+            let (p, n) = self.bucket.page_node(pgid);
+            self.stack.borrow_mut().push(elem_ref { page: p, node: n, index: 0 });
+        }
     }
 
     // Last moves the cursor to the last item in the bucket and returns its key and value.
     // If the bucket is empty then a nil key and value are returned.
     // The returned key and value are only valid for the life of the transaction.
-    pub fn last(&self) -> (Option<&'a str>, Option<&'a str>) {
-        unimplemented!()
+    pub fn last(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>) {
+        if self.closed() {
+            return (None, None);
+        }
+        {
+            let mut stack = self.stack.borrow_mut();
+            stack.clear();
+            let (p, n) = self.bucket.page_node(self.bucket.root());
+            let mut r = elem_ref { page: p, node: n, index: 0 };
+            r.index = r.count() as i64 - 1;
+            stack.push(r);
+        }
+        self._last();
+
+        let (k, v, flags) = self.key_value();
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return (k, None);
+        }
+        (k, v)
     }
 
     // moves the cursor to the last leaf element under the last page in the bucket.
     fn _last(&self) {
-        unimplemented!()
+        loop {
+            // Exit when we hit a leaf page.
+            let pgid = {
+                let stack = self.stack.borrow();
+                let r = stack.last().unwrap();
+                if r.is_leaf() {
+                    break;
+                }
+                r.pgid()
+            };
+
+            // Keep adding pages pointing to the last element in the stack.
+            let (p, n) = self.bucket.page_node(pgid);
+            let mut next = elem_ref { page: p, node: n, index: 0 };
+            next.index = next.count() as i64 - 1;
+            self.stack.borrow_mut().push(next);
+        }
     }
 
     // Next moves the cursor to the next item in the bucket and returns sits key and value.
     // If the cursor is at the end of the bucket then a nil key and value are returned.
     // The returned key and value are only valid for the life of the transaction.
-    pub fn next(&self) -> (Option<&'a str>, Option<&'a str>) {
-        unimplemented!()
+    pub fn next(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>) {
+        if self.closed() {
+            return (None, None);
+        }
+        let (k, v, flags) = self._next();
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return (k, None);
+        }
+        (k, v)
     }
 
     // moves to the next leaf element and returns the key and value.
     // If the cursor is at the last leaf element then it stays there and returns nil.
-    fn _next(&self) -> (Option<&'a str>, Option<&'a str>, u32) {
-        unimplemented!()
+    fn _next(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>, u32) {
+        loop {
+            // Attempt to move over one element until we're successful.
+            // Move up the stack as we hit the end of each page in our stack.
+            {
+                let mut stack = self.stack.borrow_mut();
+                let mut i = stack.len();
+                while i > 0 {
+                    let elem = &mut stack[i - 1];
+                    if elem.index < elem.count() as i64 - 1 {
+                        elem.index += 1;
+                        break;
+                    }
+                    i -= 1;
+                }
+
+                // If we've hit the root page then stop and return. This will leave the
+                // cursor on the last element of the last page.
+                if i == 0 {
+                    return (None, None, 0);
+                }
+
+                // Otherwise start from where we left off in the stack and find the
+                // first element of the first leaf page.
+                stack.truncate(i);
+            }
+            self._first();
+
+            // If this is an empty page then restart and move back up the stack.
+            if self.stack.borrow().last().unwrap().count() == 0 {
+                continue;
+            }
+
+            return self.key_value();
+        }
     }
 
     // Prev moves the cursor to the previous item in the bucket and returns sits key and value.
     // If the cursor is at the beginning of the bucket then a nil key and value are returned.
     // The returned key and value are only valid for the life of the transaction.
-    pub fn prev(&self) -> (Option<&'a str>, Option<&'a str>) {
-        unimplemented!()
+    pub fn prev(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>) {
+        if self.closed() {
+            return (None, None);
+        }
+        // Attempt to move back one element until we're successful.
+        // Move up the stack as we hit the beginning of each page in our stack.
+        {
+            let mut stack = self.stack.borrow_mut();
+            while !stack.is_empty() {
+                {
+                    let elem = stack.last_mut().unwrap();
+                    if elem.index > 0 {
+                        elem.index -= 1;
+                        break;
+                    }
+                }
+                stack.pop();
+            }
+
+            // If we've hit the end then return nil.
+            if stack.is_empty() {
+                return (None, None);
+            }
+        }
+
+        // Move down the stack to find the last element of the last leaf under this branch.
+        self._last();
+        let (k, v, flags) = self.key_value();
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return (k, None);
+        }
+        (k, v)
     }
 
     // Seek moves the cursor to a given key and returns it.
     // If the key does not exist then the next key is used. If no keys
     // follow, a nil key is returned.
     // The returned key and value are only valid for the life of the transaction.
-    pub fn seek(&self, seek: &'a str) -> (Option<&'a str>, Option<&'a str>) {
-        unimplemented!()
+    pub fn seek(&self, seek: &[u8]) -> (Option<&'a [u8]>, Option<&'a [u8]>) {
+        if self.closed() {
+            return (None, None);
+        }
+        let (k, v, flags) = self.seek1(seek);
+
+        // If we ended up after the last element of a page then move to the next one.
+        let past_end = {
+            let stack = self.stack.borrow();
+            let r = stack.last().unwrap();
+            r.index >= r.count() as i64
+        };
+        let (k, v, flags) = if past_end { self._next() } else { (k, v, flags) };
+
+        if k.is_none() {
+            return (None, None);
+        } else if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return (k, None);
+        }
+        (k, v)
     }
 
     // seek moves the cursor to a given key and returns it.
     // If the key does not exist then the next key is used.
-    pub fn seek1(&self, seek: &'a str) -> (Option<&'a str>, Option<&'a str>, u32) {
-        unimplemented!()
+    pub fn seek1(&self, seek: &[u8]) -> (Option<&'a [u8]>, Option<&'a [u8]>, u32) {
+        if self.closed() {
+            return (None, None, 0);
+        }
+
+        // Start from root page/node and traverse to correct page.
+        self.stack.borrow_mut().clear();
+        self.search(seek, self.bucket.root());
+
+        // If the cursor is pointing to the end of page/node then return nil.
+        self.key_value()
     }
 
     // Delete removes the current key/value under the cursor from the bucket.
     // Delete fails if current key/value is a bucket or if the transaction is not writable.
-    pub fn delete(&mut self) -> Result<(), &'static str>{
-        unimplemented!()
+    pub fn delete(&mut self) -> Result<(), Error>{
+        self.bucket.check_writable()?;
+
+        let (key, _, flags) = self.key_value();
+
+        // Return an error if current value is a bucket.
+        if (flags & BUCKET_LEAF_FLAG as u32) != 0 {
+            return Err(Error::IncompatibleValue);
+        }
+        if let Some(key) = key {
+            self.node().borrow_mut().del(key);
+        }
+        Ok(())
     }
 
     // search recursively performs a binary search against a given page/node until it finds a given key.
-    fn search(&self, key: &'a str, pgid: pgid_t) {
-        unimplemented!()
+    fn search(&self, key: &[u8], pgid: pgid_t) {
+        let (p, n) = self.bucket.page_node(pgid);
+        if let Some(p) = p {
+            let flags = p.flags;
+            if (flags & (BRANCH_PAGE_FLAG | LEAF_PAGE_FLAG)) == 0 {
+                panic!("invalid page type: {}: {:x}", pgid, flags);
+            }
+        }
+        let e = elem_ref { page: p, node: n.clone(), index: 0 };
+        let is_leaf = e.is_leaf();
+        self.stack.borrow_mut().push(e);
+
+        // If we're on a leaf page/node then find the specific node.
+        if is_leaf {
+            self.nsearch(key);
+            return;
+        }
+
+        match n {
+            Some(n) => self.search_node(key, n),
+            None => self.search_page(key, p.unwrap()),
+        }
     }
 
-    fn search_node(&self, key: &'a str, n: Rc<RefCell<Node<'a>>>) {
-        unimplemented!()
+    fn search_node(&self, key: &[u8], n: Rc<RefCell<Node<'a>>>) {
+        let (index, pgid) = {
+            let n = n.borrow();
+            let index = match n.inodes.binary_search_by(|inode| inode.key.cmp(key)) {
+                Ok(i) => i,
+                Err(i) => if i > 0 { i - 1 } else { i },
+            };
+            (index, n.inodes[index].pgid)
+        };
+        self.stack.borrow_mut().last_mut().unwrap().index = index as i64;
+
+        // Recursively search to the next page.
+        self.search(key, pgid);
     }
 
-    fn search_page(&self, key: &'a str, p: Rc<RefCell<Page>>) {
-        unimplemented!()
+    fn search_page(&self, key: &[u8], p: &'a Page) {
+        // Binary search for the correct range.
+        let inodes = &p.branch_page_elements()[..p.count as usize];
+        let index = match inodes.binary_search_by(|elem| elem.key().cmp(key)) {
+            Ok(i) => i,
+            Err(i) => if i > 0 { i - 1 } else { i },
+        };
+        self.stack.borrow_mut().last_mut().unwrap().index = index as i64;
+
+        // Recursively search to the next page.
+        self.search(key, inodes[index].pgid);
     }
 
     // nsearch searches the leaf node on the top of the stack for a key.
-    fn nsearch(&self, key: &'a str) {
-        unimplemented!()
+    fn nsearch(&self, key: &[u8]) {
+        let mut stack = self.stack.borrow_mut();
+        let e = stack.last_mut().unwrap();
+
+        let index = match e.node {
+            // If we have a node then search its inodes.
+            Some(ref n) => match n.borrow().inodes.binary_search_by(|inode| inode.key.cmp(key)) {
+                Ok(i) | Err(i) => i,
+            },
+            // If we have a page then search its leaf elements.
+            None => {
+                let p = e.page.unwrap();
+                let inodes = &p.leaf_page_elements()[..p.count as usize];
+                match inodes.binary_search_by(|elem| elem.key().cmp(key)) {
+                    Ok(i) | Err(i) => i,
+                }
+            },
+        };
+        e.index = index as i64;
     }
 
     // returns the key and value of the current leaf element.
-    fn key_value(&self) -> (&'a str, Option<&'a str>, u32) {
-        unimplemented!()
+    fn key_value(&self) -> (Option<&'a [u8]>, Option<&'a [u8]>, u32) {
+        let stack = self.stack.borrow();
+        let r = stack.last().unwrap();
+        if r.count() == 0 || r.index < 0 || r.index as usize >= r.count() {
+            return (None, None, 0);
+        }
+
+        // Retrieve value from node.
+        if let Some(ref n) = r.node {
+            let n = n.borrow();
+            let inode = &n.inodes[r.index as usize];
+            return (Some(inode.key), inode.value, inode.flags);
+        }
+
+        // Or retrieve value from page.
+        let elem = unsafe { &*r.page.unwrap().leaf_page_element(r.index as u16) };
+        (
+            Some(elem.key()),
+            Some(elem.value()),
+            elem.flags,
+        )
+    }
+
+    // node returns the node that the cursor is currently positioned on.
+    pub(crate) fn node(&self) -> Rc<RefCell<Node<'a>>> {
+        let stack = self.stack.borrow();
+        assert!(stack.len() > 0, "accessing a node with a zero-length cursor stack");
+
+        // If the top of the stack is a leaf node then just return it.
+        {
+            let r = stack.last().unwrap();
+            if let Some(ref n) = r.node {
+                if r.is_leaf() {
+                    return Rc::clone(n);
+                }
+            }
+        }
+
+        // Start from root and traverse down the hierarchy.
+        let mut n = match stack[0].node {
+            Some(ref n) => Rc::clone(n),
+            None => self.bucket.node(stack[0].page.unwrap().id, None),
+        };
+        for r in &stack[..stack.len() - 1] {
+            assert!(!n.borrow().is_leaf, "expected branch node");
+            let child = n.borrow().child_at(r.index as usize);
+            n = child;
+        }
+        assert!(n.borrow().is_leaf, "expected leaf node");
+        n
+    }
+}
+
+// Iter is a double-ended iterator over the key/value pairs of a bucket in sorted order.
+// Nested buckets are returned with a nil value, the same as with a cursor.
+// The front and back are walked by separate cursors and iteration stops once they
+// meet or run past the bounds of the range.
+pub struct Iter<'a> {
+    front: Rc<RefCell<Cursor<'a>>>,
+    back: Rc<RefCell<Cursor<'a>>>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    front_key: Option<&'a [u8]>, // last key returned from the front
+    back_key: Option<&'a [u8]>,  // last key returned from the back
+    done: bool,
+}
+
+impl<'a> Iter<'a> {
+    pub(crate) fn new(bucket: &Bucket<'a>) -> Iter<'a> {
+        Iter::range(bucket, &(..))
+    }
+
+    // range returns an iterator over the keys of a bucket that fall within range.
+    pub(crate) fn range<R: KeyRange>(bucket: &Bucket<'a>, range: &R) -> Iter<'a> {
+        Iter::bounded(bucket, owned_bound(range.start_key()), owned_bound(range.end_key()))
+    }
+
+    // prefix returns an iterator over the keys of a bucket that start with prefix.
+    // The scan ends before the first key greater than every key with the prefix.
+    pub(crate) fn prefix(bucket: &Bucket<'a>, prefix: &[u8]) -> Iter<'a> {
+        let end = match prefix_end(prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        Iter::bounded(bucket, Bound::Included(prefix.to_vec()), end)
+    }
+
+    fn bounded(bucket: &Bucket<'a>, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Iter<'a> {
+        Iter {
+            front: bucket.cursor(),
+            back: bucket.cursor(),
+            start: start,
+            end: end,
+            front_key: None,
+            back_key: None,
+            done: false,
+        }
+    }
+
+    // returns true if key is not past the end of the range.
+    fn before_end(&self, key: &[u8]) -> bool {
+        match self.end {
+            Bound::Included(ref end) => key <= &end[..],
+            Bound::Excluded(ref end) => key < &end[..],
+            Bound::Unbounded => true,
+        }
+    }
+
+    // returns true if key is not before the start of the range.
+    fn after_start(&self, key: &[u8]) -> bool {
+        match self.start {
+            Bound::Included(ref start) => key >= &start[..],
+            Bound::Excluded(ref start) => key > &start[..],
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], Option<&'a [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (k, v) = {
+            let c = self.front.borrow();
+            if self.front_key.is_some() {
+                c.next()
+            } else {
+                // Position the cursor on the first key in the range.
+                match self.start {
+                    Bound::Unbounded => c.first(),
+                    Bound::Included(ref start) => c.seek(start),
+                    Bound::Excluded(ref start) => match c.seek(start) {
+                        (Some(k), _) if k == &start[..] => c.next(),
+                        kv => kv,
+                    },
+                }
+            }
+        };
+
+        // Stop at the end of the range or where the back of the iterator left off.
+        match k {
+            Some(k) if self.before_end(k) && self.back_key.map_or(true, |back| k < back) => {
+                self.front_key = Some(k);
+                Some((k, v))
+            },
+            _ => {
+                self.done = true;
+                None
+            },
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (k, v) = {
+            let c = self.back.borrow();
+            if self.back_key.is_some() {
+                c.prev()
+            } else {
+                // Position the cursor on the last key in the range. Seeking past
+                // the last key leaves the cursor at the end so start from last().
+                match self.end {
+                    Bound::Unbounded => c.last(),
+                    Bound::Included(ref end) => match c.seek(end) {
+                        (Some(k), v) if k == &end[..] => (Some(k), v),
+                        (Some(_), _) => c.prev(),
+                        (None, _) => c.last(),
+                    },
+                    Bound::Excluded(ref end) => match c.seek(end) {
+                        (Some(_), _) => c.prev(),
+                        (None, _) => c.last(),
+                    },
+                }
+            }
+        };
+
+        // Stop at the start of the range or where the front of the iterator left off.
+        match k {
+            Some(k) if self.after_start(k) && self.front_key.map_or(true, |front| k > front) => {
+                self.back_key = Some(k);
+                Some((k, v))
+            },
+            _ => {
+                self.done = true;
+                None
+            },
+        }
+    }
+}
+
+// prefix_end returns the smallest key greater than all keys starting with prefix.
+// Returns None if there is no such key, i.e. the prefix is empty or all 0xff bytes.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(b) = end.pop() {
+        if b < 0xff {
+            end.push(b + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+// owned_bound copies the key of a range bound so the iterator doesn't borrow it.
+fn owned_bound(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+// KeyRange is a range of keys that can be passed to Bucket::range. It is implemented
+// for every standard range type over keys that can be viewed as bytes, such as
+// `b"a"..b"c"`, `Vec<u8>` and `String` ranges, `(Bound, Bound)` pairs and `..`.
+pub trait KeyRange {
+    // start_key returns the lower bound of the range.
+    fn start_key(&self) -> Bound<&[u8]>;
+
+    // end_key returns the upper bound of the range.
+    fn end_key(&self) -> Bound<&[u8]>;
+}
+
+// key_bound views the key of a range bound as bytes.
+fn key_bound<K: AsRef<[u8]>>(bound: Bound<&K>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_ref()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_ref()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+macro_rules! impl_key_range {
+    ($($range:ty),*) => {$(
+        impl<K: AsRef<[u8]>> KeyRange for $range {
+            fn start_key(&self) -> Bound<&[u8]> {
+                key_bound(RangeBounds::<K>::start_bound(self))
+            }
+
+            fn end_key(&self) -> Bound<&[u8]> {
+                key_bound(RangeBounds::<K>::end_bound(self))
+            }
+        }
+    )*}
+}
+
+impl_key_range!(Range<K>, RangeFrom<K>, RangeInclusive<K>, RangeTo<K>, RangeToInclusive<K>, (Bound<K>, Bound<K>));
+
+impl KeyRange for RangeFull {
+    fn start_key(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
+    }
+
+    fn end_key(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
     }
 }
 
 // elem_ref represents a reference to an element on a given page/node.
 pub struct elem_ref<'a> {
-    pub page: Rc<RefCell<Page>>,
+    pub page: Option<&'a Page>,
     pub node: Option<Rc<RefCell<Node<'a>>>>,
     pub index: i64,
 }
@@ -132,14 +634,315 @@ impl<'a> elem_ref<'a> {
     fn is_leaf(&self) -> bool {
         match self.node {
             Some(ref n) => n.borrow().is_leaf,
-            None => (self.page.borrow().flags & LEAF_PAGE_FLAG) != 0,
+            None => (self.page.unwrap().flags & LEAF_PAGE_FLAG) != 0,
         }
     }
 
     fn count(&self) -> usize {
         match self.node {
             Some(ref n) => n.borrow().inodes.len(),
-            None => self.page.borrow().count as usize,
+            None => self.page.unwrap().count as usize,
+        }
+    }
+
+    // returns the child page id at the current index of a branch page/node.
+    fn pgid(&self) -> pgid_t {
+        match self.node {
+            Some(ref n) => n.borrow().inodes[self.index as usize].pgid,
+            None => unsafe { (*self.page.unwrap().branch_page_element(self.index as u16)).pgid },
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use db::DB;
+    use errors::Error;
+    use std::ops::Bound;
+    use super::Iter;
+    use db::tests::TestDB;
+
+    #[test]
+    fn cursor_empty_bucket() {
+        let db = TestDB::new();
+        DB::view(&db, |tx| {
+            let c = tx.root_bucket().cursor();
+            assert_eq!(c.borrow().first(), (None, None));
+            assert_eq!(c.borrow().last(), (None, None));
+            assert_eq!(c.borrow().seek(b"foo"), (None, None));
+            assert_eq!(c.borrow().next(), (None, None));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn cursor_materialized_node() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            {
+                let n = root.node(3, None);
+                let mut n = n.borrow_mut();
+                n.put(b"baz", b"baz", Some(&b"2"[..]), 0, 0);
+                n.put(b"foo", b"foo", Some(&b"3"[..]), 0, 0);
+                n.put(b"bar", b"bar", Some(&b"1"[..]), 0, 0);
+            }
+
+            let c = root.cursor();
+            let c = c.borrow();
+            assert_eq!(c.first(), (Some(&b"bar"[..]), Some(&b"1"[..])));
+            assert_eq!(c.next(), (Some(&b"baz"[..]), Some(&b"2"[..])));
+            assert_eq!(c.next(), (Some(&b"foo"[..]), Some(&b"3"[..])));
+            assert_eq!(c.next(), (None, None));
+
+            assert_eq!(c.last(), (Some(&b"foo"[..]), Some(&b"3"[..])));
+            assert_eq!(c.prev(), (Some(&b"baz"[..]), Some(&b"2"[..])));
+            assert_eq!(c.prev(), (Some(&b"bar"[..]), Some(&b"1"[..])));
+            assert_eq!(c.prev(), (None, None));
+
+            assert_eq!(c.seek(b"bas"), (Some(&b"baz"[..]), Some(&b"2"[..])));
+            assert_eq!(c.seek(b"baz"), (Some(&b"baz"[..]), Some(&b"2"[..])));
+            assert_eq!(c.seek(b"zzz"), (None, None));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn cursor_branch_pages() {
+        let db = TestDB::new();
+        let count = 1000;
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..count {
+                let k = format!("{:05}", i);
+                b.put(k.as_bytes(), k.as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let root = tx.root_bucket();
+            assert!(root.root_node.borrow().is_none());
+            let c = root.cursor();
+            let c = c.borrow();
+
+            // Iterate forward over every leaf page in order.
+            let mut i = 0;
+            let (mut k, mut v) = c.first();
+            while let Some(key) = k {
+                assert_eq!(key, format!("{:05}", i).as_bytes());
+                assert_eq!(v, Some(key));
+                i += 1;
+                let next = c.next();
+                k = next.0;
+                v = next.1;
+            }
+            assert_eq!(i, count);
+
+            // And backward.
+            let (mut k, _) = c.last();
+            while let Some(key) = k {
+                i -= 1;
+                assert_eq!(key, format!("{:05}", i).as_bytes());
+                k = c.prev().0;
+            }
+            assert_eq!(i, 0);
+
+            assert_eq!(c.seek(b"00500").0, Some(&b"00500"[..]));
+            assert_eq!(c.seek(b"00500a").0, Some(&b"00501"[..]));
+            assert_eq!(c.next().0, Some(&b"00502"[..]));
+            assert_eq!(c.seek(b"").0, Some(&b"00000"[..]));
+            assert_eq!(c.seek(b"01000").0, None);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn cursor_delete() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..1000 {
+                let k = format!("{:05}", i);
+                b.put(k.as_bytes(), k.as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+
+        DB::update(&db, |tx| {
+            let c = tx.root_bucket().cursor();
+            let mut c = c.borrow_mut();
+            c.seek(b"00500");
+            c.delete().unwrap();
+            assert_eq!(c.seek(b"00500").0, Some(&b"00501"[..]));
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let c = tx.root_bucket().cursor();
+            let mut c = c.borrow_mut();
+            assert_eq!(c.seek(b"00500").0, Some(&b"00501"[..]));
+            assert_eq!(c.delete(), Err(Error::TxNotWritable));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_iter() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..1000 {
+                let k = format!("{:05}", i);
+                b.put(k.as_bytes(), k.as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            let keys: Vec<&[u8]> = b.iter().map(|(k, _)| k).collect();
+            assert_eq!(keys.len(), 1000);
+            assert_eq!(keys[0], b"00000");
+            assert_eq!(keys[999], b"00999");
+            assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+            let last: Vec<&[u8]> = b.iter().rev().take(2).map(|(k, _)| k).collect();
+            assert_eq!(last, vec![&b"00999"[..], &b"00998"[..]]);
+
+            assert_eq!(b.iter().filter(|&(k, _)| k.ends_with(b"7")).count(), 100);
+            assert_eq!(b.iter().next(), Some((&b"00000"[..], Some(&b"00000"[..]))));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_iter_both_ends() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for k in &[b"a", b"b", b"c", b"d", b"e"] {
+                b.put(*k, b"")?;
+            }
+
+            // The ends meet in the middle without repeating an entry.
+            let mut iter = b.iter();
+            assert_eq!(iter.next().unwrap().0, b"a");
+            assert_eq!(iter.next_back().unwrap().0, b"e");
+            assert_eq!(iter.next_back().unwrap().0, b"d");
+            assert_eq!(iter.next().unwrap().0, b"b");
+            assert_eq!(iter.next().unwrap().0, b"c");
+            assert_eq!(iter.next_back(), None);
+            assert_eq!(iter.next(), None);
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            assert_eq!(b.iter().rev().count(), 5);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_iter_empty() {
+        let db = TestDB::new();
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            assert_eq!(b.iter().next(), None);
+            assert_eq!(b.iter().next_back(), None);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_range() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for k in &[b"b", b"d", b"f", b"h"] {
+                b.put(*k, b"")?;
+            }
+
+            let keys = |iter: Iter| iter.map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+            let v = |ks: &[&[u8]]| ks.iter().map(|k| k.to_vec()).collect::<Vec<_>>();
+            assert_eq!(keys(b.range(b"d"..b"h")), v(&[b"d", b"f"]));
+            assert_eq!(keys(b.range(b"d"..=b"h")), v(&[b"d", b"f", b"h"]));
+            assert_eq!(keys(b.range(b"c"..b"g")), v(&[b"d", b"f"]));
+            assert_eq!(keys(b.range(..b"d")), v(&[b"b"]));
+            assert_eq!(keys(b.range(b"e"..)), v(&[b"f", b"h"]));
+            assert_eq!(keys(b.range(..)), v(&[b"b", b"d", b"f", b"h"]));
+            assert_eq!(keys(b.range((Bound::Excluded(b"b"), Bound::Excluded(b"h")))), v(&[b"d", b"f"]));
+            assert_eq!(keys(b.range(b"i"..)), v(&[]));
+            assert_eq!(keys(b.range(b"d"..b"d")), v(&[]));
+            assert_eq!(keys(b.range(b"d".to_vec()..)), v(&[b"d", b"f", b"h"]));
+            assert_eq!(keys(b.range(String::from("c")..String::from("g"))), v(&[b"d", b"f"]));
+            assert_eq!(keys(b.range("c"..="f")), v(&[b"d", b"f"]));
+
+            // Reverse iteration yields the same range backwards.
+            assert_eq!(keys(b.range(b"c"..=b"h")).len(), 3);
+            assert_eq!(b.range(b"c"..=b"h").rev().map(|(k, _)| k.to_vec()).collect::<Vec<_>>(), v(&[b"h", b"f", b"d"]));
+            assert_eq!(b.range(b"c"..b"h").rev().map(|(k, _)| k.to_vec()).collect::<Vec<_>>(), v(&[b"f", b"d"]));
+            assert_eq!(b.range(..=b"z").rev().map(|(k, _)| k.to_vec()).collect::<Vec<_>>(), v(&[b"h", b"f", b"d", b"b"]));
+            assert_eq!(b.range(..b"b").next_back(), None);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_range_pages() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0; 100])?;
+            }
+            Ok(())
+        }).unwrap();
+
+        // Ranges spanning several leaf pages are walked in order from either end.
+        DB::view(&db, |tx| {
+            let b = tx.root_bucket();
+            let fwd: Vec<_> = b.range(b"0100"..b"0900").map(|(k, _)| k.to_vec()).collect();
+            assert_eq!(fwd.len(), 800);
+            assert_eq!(fwd[0], b"0100");
+            assert_eq!(fwd[799], b"0899");
+            let mut rev: Vec<_> = b.range(b"0100"..b"0900").rev().map(|(k, _)| k.to_vec()).collect();
+            rev.reverse();
+            assert_eq!(fwd, rev);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn bucket_scan_prefix() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let b = tx.root_bucket();
+            for k in &[&b"user/1"[..], b"user/12/a", b"user/12/b", b"user/123", b"user/2", b"users", b"\xff\xff"] {
+                b.put(k, b"")?;
+            }
+
+            let keys = |iter: Iter| iter.map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+            let rkeys = |iter: Iter| iter.rev().map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+            let v = |ks: &[&[u8]]| ks.iter().map(|k| k.to_vec()).collect::<Vec<_>>();
+            assert_eq!(keys(b.scan_prefix(b"user/12")), v(&[b"user/12/a", b"user/12/b", b"user/123"]));
+            assert_eq!(keys(b.scan_prefix(b"user/12/")), v(&[b"user/12/a", b"user/12/b"]));
+            assert_eq!(keys(b.scan_prefix(b"user/3")), v(&[]));
+            assert_eq!(keys(b.scan_prefix(b"\xff")), v(&[b"\xff\xff"]));
+            assert_eq!(keys(b.scan_prefix(b"")).len(), 7);
+
+            // Reverse scans stop at the same boundaries.
+            assert_eq!(rkeys(b.scan_prefix(b"user/")), v(&[b"user/2", b"user/123", b"user/12/b", b"user/12/a", b"user/1"]));
+            assert_eq!(rkeys(b.scan_prefix(b"\xff")), v(&[b"\xff\xff"]));
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn prefix_end() {
+        assert_eq!(super::prefix_end(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(super::prefix_end(b"a\xff"), Some(b"b".to_vec()));
+        assert_eq!(super::prefix_end(b"\xff\xff"), None);
+        assert_eq!(super::prefix_end(b""), None);
+    }
+}
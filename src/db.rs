@@ -2,9 +2,38 @@ use types::pgid_t;
 use types::txid_t;
 use bucket::_Bucket;
 use freelist::FreeList;
-use std::rc::Rc;
-use std::cell::RefCell;
+use tx::{Tx, TxHandle};
+use meta;
+use errors::Error;
+use page::{get_page_header_size, Page, META_PAGE_FLAG, FREELIST_PAGE_FLAG, LEAF_PAGE_FLAG};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::cmp;
+use std::mem;
+use std::io;
+use libc;
 
+// The data file format version.
+pub const VERSION: u32 = 2;
+
+// Represents a marker value to indicate that a file is a Bolt DB.
+pub const MAGIC: u32 = 0xED0CDAED;
+
+// Default page size for newly created databases.
+pub const DEFAULT_PAGE_SIZE: usize = 4 * 1024;
+
+// The largest step that can be taken when remapping the mmap.
+pub const MAX_MMAP_STEP: usize = 1 << 30; // 1GB
+
+// The largest mmap size supported.
+pub const MAX_MAP_SIZE: usize = 0xFFFFFFFFFFFF; // 256TB
+
+#[derive(Clone)]
 pub struct Meta {
     pub magic: u32,
     pub version: u32,
@@ -31,21 +60,944 @@ impl Meta {
             checksum: 0,
         }
     }
+
+    // read copies the meta stored on a meta page.
+    pub fn read(p: &Page) -> Meta {
+        let m = unsafe { &*p.meta() };
+        Meta {
+            magic: m.magic,
+            version: m.version,
+            page_size: m.page_size,
+            flags: m.flags,
+            root: _Bucket {
+                root: m.root.root,
+                sequence: m.root.sequence,
+            },
+            freelist: m.freelist,
+            pgid: m.pgid,
+            txid: m.txid,
+            checksum: m.checksum,
+        }
+    }
+
+    // write writes the meta onto a page.
+    pub fn write(&self, p: &mut Page) {
+        if self.root.root >= self.pgid {
+            panic!("root bucket pgid ({}) above high water mark ({})", self.root.root, self.pgid);
+        } else if self.freelist >= self.pgid {
+            panic!("freelist pgid ({}) above high water mark ({})", self.freelist, self.pgid);
+        }
+
+        // Page id is either going to be 0 or 1 which we can determine by the transaction ID.
+        p.id = self.txid % 2;
+        p.flags |= META_PAGE_FLAG;
+
+        let m = p.meta() as *mut meta::Meta;
+        unsafe {
+            (*m).magic = self.magic;
+            (*m).version = self.version;
+            (*m).page_size = self.page_size;
+            (*m).flags = self.flags;
+            (*m).root = _Bucket {
+                root: self.root.root,
+                sequence: self.root.sequence,
+            };
+            (*m).freelist = self.freelist;
+            (*m).pgid = self.pgid;
+            (*m).txid = self.txid;
+            (*m).checksum = (*m).sum64();
+        }
+    }
 }
 
-pub struct DB {
+// Options represents the options that can be set when opening a database.
+pub struct Options {
+    // Page size used when a new database file is created. Existing files
+    // always keep the page size they were created with.
     pub page_size: usize,
 
-    // TODO: need to use mutex
-    pub freelist: Rc<RefCell<FreeList>>,
+    // Initial mmap size of the database in bytes. Read transactions won't
+    // block write transactions if the initial mmap size is large enough to
+    // hold the database.
+    pub initial_mmap_size: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            page_size: DEFAULT_PAGE_SIZE,
+            initial_mmap_size: 0,
+        }
+    }
+}
+
+// DB represents a collection of buckets persisted to a file on disk.
+// A DB is safe to share between threads behind an Arc. Any number of read
+// transactions can be open at once but only one write transaction can be
+// active at a time.
+pub struct DB {
+    pub(crate) path: PathBuf,
+    pub(crate) file: Mutex<Option<File>>,
+    pub(crate) page_size: usize,
+    opened: AtomicBool,
+
+    data: AtomicPtr<u8>, // start of the read-only mmap of the data file
+    datasz: AtomicUsize,
+    filesz: AtomicUsize, // current on disk file size
+
+    rwlock: Mutex<bool>, // set while a write transaction is open
+    rwcond: Condvar,     // signalled when the write transaction closes
+
+    mmaplock: Mutex<Vec<txid_t>>, // txids of open read transactions; held while remapping
+    mmapcond: Condvar,      // signalled when a read transaction closes
+
+    pub(crate) freelist: Mutex<FreeList>,
 }
 
 
 impl DB {
-    pub fn new() -> DB {
+    pub(crate) fn new() -> DB {
         DB {
-            page_size: 4 * 1024,
-            freelist: Rc::new(RefCell::new(FreeList::new())),
+            path: PathBuf::new(),
+            file: Mutex::new(None),
+            page_size: DEFAULT_PAGE_SIZE,
+            opened: AtomicBool::new(false),
+            data: AtomicPtr::new(ptr::null_mut()),
+            datasz: AtomicUsize::new(0),
+            filesz: AtomicUsize::new(0),
+            rwlock: Mutex::new(false),
+            rwcond: Condvar::new(),
+            mmaplock: Mutex::new(Vec::new()),
+            mmapcond: Condvar::new(),
+            freelist: Mutex::new(FreeList::new()),
+        }
+    }
+
+    // opened returns true while the database is open.
+    pub fn opened(&self) -> bool {
+        self.opened.load(Ordering::SeqCst)
+    }
+
+    // datasz returns the size of the current mmap in bytes.
+    pub fn datasz(&self) -> usize {
+        self.datasz.load(Ordering::SeqCst)
+    }
+
+    // page_size returns the size of a page in the data file.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    // path returns the path to the data file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // filesz returns the current on disk size of the data file.
+    pub fn filesz(&self) -> usize {
+        self.filesz.load(Ordering::SeqCst)
+    }
+
+    // open creates and opens a database at the given path.
+    // If the file does not exist then it will be created automatically.
+    pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<DB, Error> {
+        let mut db = DB::new();
+        db.path = path.as_ref().to_path_buf();
+
+        let file = match OpenOptions::new().read(true).write(true).create(true).open(&db.path) {
+            Ok(f) => f,
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let size = match file.metadata() {
+            Ok(info) => info.len(),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        *db.file.lock().unwrap() = Some(file);
+
+        // Initialize the database if it doesn't exist.
+        if size == 0 {
+            db.page_size = options.page_size;
+            db.init()?;
+        } else {
+            // Read the page size from the first valid meta page.
+            // If neither meta page is valid then fall back to the default page
+            // size and let the meta validation in mmap() report the error.
+            db.page_size = db.read_page_size(size)?.unwrap_or(options.page_size);
+        }
+
+        // Memory map the data file.
+        db.mmap(options.initial_mmap_size)?;
+
+        // Read in the freelist.
+        let freelist_pgid = db.meta().freelist;
+        db.freelist.lock().unwrap().read(db.page(freelist_pgid));
+
+        db.opened.store(true, Ordering::SeqCst);
+        Ok(db)
+    }
+
+    // begin starts a new transaction.
+    // Multiple read-only transactions can be used concurrently but only one
+    // write transaction can be used at a time. Starting multiple write transactions
+    // will cause the calls to block and be serialized until the current write
+    // transaction finishes.
+    //
+    // Transactions should not be dependent on one another. Opening a read
+    // transaction and a write transaction in the same thread may cause the
+    // writer to deadlock because the database periodically needs to re-mmap itself
+    // as it grows and it cannot do that while a read transaction is open.
+    pub fn begin(db: &Arc<DB>, writable: bool) -> Result<TxHandle, Error> {
+        if writable {
+            DB::begin_rwtx(db)
+        } else {
+            DB::begin_tx(db)
+        }
+    }
+
+    fn begin_tx(db: &Arc<DB>) -> Result<TxHandle, Error> {
+        // Register as a reader so the mmap cannot be remapped while this
+        // transaction is open.
+        let mut txs = db.mmaplock.lock().unwrap();
+        if !db.opened() {
+            return Err(Error::DatabaseNotOpen);
         }
+
+        let tx = Tx::new(db);
+        tx.locked.set(true);
+        let tx = TxHandle::new(tx);
+
+        // Keep track of the transaction so its pages aren't reused until it closes.
+        txs.push(tx.meta.borrow().txid);
+        Ok(tx)
+    }
+
+    fn begin_rwtx(db: &Arc<DB>) -> Result<TxHandle, Error> {
+        // Obtain writer lock. This is released by the transaction when it closes.
+        // This enforces only one writer transaction at a time.
+        {
+            let mut locked = db.rwlock.lock().unwrap();
+            while *locked {
+                locked = db.rwcond.wait(locked).unwrap();
+            }
+            if !db.opened() {
+                return Err(Error::DatabaseNotOpen);
+            }
+            *locked = true;
+        }
+
+        let mut tx = Tx::new(db);
+        tx.writable = true;
+        tx.locked.set(true);
+        let tx = TxHandle::new(tx);
+
+        // Free any pages associated with closed read-only transactions.
+        // Pages freed at or after the oldest open reader are still visible to it.
+        let minid = db.mmaplock.lock().unwrap().iter().cloned().min().unwrap_or(txid_t::max_value());
+        if minid > 0 {
+            db.freelist.lock().unwrap().release(minid - 1);
+        }
+        Ok(tx)
+    }
+
+    // remove_tx releases the lock held by a transaction that is closing.
+    pub(crate) fn remove_tx(&self, tx: &Tx) {
+        if tx.writable {
+            *self.rwlock.lock().unwrap() = false;
+            self.rwcond.notify_one();
+        } else {
+            let mut txs = self.mmaplock.lock().unwrap();
+            let txid = tx.meta.borrow().txid;
+            if let Some(i) = txs.iter().position(|&id| id == txid) {
+                txs.swap_remove(i);
+            }
+            self.mmapcond.notify_all();
+        }
+    }
+
+    // update executes a function within the context of a read-write managed transaction.
+    // If no error is returned from the function then the transaction is committed.
+    // If an error is returned then the entire transaction is rolled back.
+    // Any error that is returned from the function or returned from the commit is
+    // returned from the update() method.
+    //
+    // Attempting to manually commit or rollback within the function will return an error.
+    pub fn update<F>(db: &Arc<DB>, f: F) -> Result<(), Error>
+    where F: FnOnce(&Tx) -> Result<(), Error> {
+        let tx = DB::begin(db, true)?;
+
+        // Mark as a managed tx so that the inner function cannot manually commit.
+        // If the function panics the transaction is rolled back when it is dropped.
+        tx.managed.set(true);
+
+        // If an error is returned from the function then rollback and return error.
+        let result = f(&tx);
+        tx.managed.set(false);
+        if let Err(e) = result {
+            let _ = tx.rollback();
+            return Err(e);
+        }
+
+        tx.commit()
+    }
+
+    // view executes a function within the context of a managed read-only transaction.
+    // Any error that is returned from the function is returned from the view() method.
+    //
+    // Attempting to manually rollback within the function will return an error.
+    pub fn view<F>(db: &Arc<DB>, f: F) -> Result<(), Error>
+    where F: FnOnce(&Tx) -> Result<(), Error> {
+        let tx = DB::begin(db, false)?;
+
+        // Mark as a managed tx so that the inner function cannot manually rollback.
+        tx.managed.set(true);
+
+        // If an error is returned from the function then pass it through.
+        let result = f(&tx);
+        tx.managed.set(false);
+        tx.rollback()?;
+        result
+    }
+
+    // init creates a new database file and initializes its meta pages.
+    fn init(&self) -> Result<(), Error> {
+        // Create two meta pages on a buffer.
+        let mut buf = vec![0u8; self.page_size * 4];
+        for i in 0..2 {
+            let p = self.page_in_buffer(&mut buf, i);
+            p.id = i;
+            p.flags = META_PAGE_FLAG;
+
+            // Initialize the meta page.
+            let mut m = Meta::new();
+            m.magic = MAGIC;
+            m.version = VERSION;
+            m.page_size = self.page_size as u32;
+            m.freelist = 2;
+            m.root = _Bucket { root: 3, sequence: 0 };
+            m.pgid = 4;
+            m.txid = i as txid_t;
+            m.write(p);
+        }
+
+        // Write an empty freelist at page 2.
+        {
+            let p = self.page_in_buffer(&mut buf, 2);
+            p.id = 2;
+            p.flags = FREELIST_PAGE_FLAG;
+            p.count = 0;
+        }
+
+        // Write an empty leaf page at page 3.
+        {
+            let p = self.page_in_buffer(&mut buf, 3);
+            p.id = 3;
+            p.flags = LEAF_PAGE_FLAG;
+            p.count = 0;
+        }
+
+        // Write the buffer to our data file.
+        self.write_at(&buf, 0)?;
+        self.sync()
+    }
+
+    // mmap opens the underlying memory-mapped file and initializes the meta references.
+    // minsz is the minimum size that the new mmap can be.
+    // Blocks until all open read transactions have closed.
+    pub(crate) fn mmap(&self, minsz: usize) -> Result<(), Error> {
+        // Read transactions hold pointers into the current mapping so wait for
+        // them to finish. Holding the lock keeps new readers out until we're done.
+        let mut txs = self.mmaplock.lock().unwrap();
+        while !txs.is_empty() {
+            txs = self.mmapcond.wait(txs).unwrap();
+        }
+
+        let file = self.file.lock().unwrap();
+        let mut size = match *file {
+            None => return Err(Error::DatabaseNotOpen),
+            Some(ref f) => match f.metadata() {
+                Ok(info) => info.len() as usize,
+                Err(e) => return Err(Error::Io(e)),
+            },
+        };
+        if size < self.page_size * 2 {
+            return Err(Error::Invalid);
+        }
+        self.filesz.store(size, Ordering::SeqCst);
+
+        // Ensure the size is at least the minimum size.
+        size = cmp::max(size, minsz);
+        size = self.mmap_size(size)?;
+
+        // Unmap existing data before continuing.
+        self.munmap()?;
+
+        // Memory-map the data file as a byte slice.
+        let fd = file.as_ref().unwrap().as_raw_fd();
+        let data = unsafe {
+            libc::mmap(ptr::null_mut(), size, libc::PROT_READ, libc::MAP_SHARED, fd, 0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+
+        // Save the original byte slice and convert to a byte array pointer.
+        self.data.store(data as *mut u8, Ordering::SeqCst);
+        self.datasz.store(size, Ordering::SeqCst);
+
+        // Validate the meta pages. We only return an error if both meta pages fail
+        // validation, since meta0 failing validation means that it wasn't saved
+        // properly -- but we can recover using meta1. And vice-versa.
+        let err0 = unsafe { (*self.page(0).meta()).validate() };
+        let err1 = unsafe { (*self.page(1).meta()).validate() };
+        if err0.is_err() && err1.is_err() {
+            return err0;
+        }
+        Ok(())
+    }
+
+    // grow grows the size of the database file to the given sz.
+    pub(crate) fn grow(&self, sz: usize) -> Result<(), Error> {
+        // Ignore if the new size is less than available file size.
+        if sz <= self.filesz() {
+            return Ok(());
+        }
+
+        match *self.file.lock().unwrap() {
+            None => return Err(Error::DatabaseNotOpen),
+            Some(ref f) => {
+                f.set_len(sz as u64)?;
+            },
+        }
+        self.sync()?;
+        self.filesz.store(sz, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // munmap unmaps the data file from memory.
+    // The caller must hold the mmap lock.
+    fn munmap(&self) -> Result<(), Error> {
+        // Ignore the unmap if we have no mapped data.
+        let data = self.data.load(Ordering::SeqCst);
+        if data.is_null() {
+            return Ok(());
+        }
+
+        let err = unsafe { libc::munmap(data as *mut libc::c_void, self.datasz()) };
+        let os_err = io::Error::last_os_error();
+        self.data.store(ptr::null_mut(), Ordering::SeqCst);
+        self.datasz.store(0, Ordering::SeqCst);
+        if err != 0 {
+            return Err(Error::Io(os_err));
+        }
+        Ok(())
+    }
+
+    // mmap_size determines the appropriate size for the mmap given the current size
+    // of the database. The minimum size is 32KB and doubles until it reaches 1GB.
+    // Returns an error if the new mmap size is greater than the max allowed.
+    fn mmap_size(&self, size: usize) -> Result<usize, Error> {
+        // Double the size from 32KB until 1GB.
+        for i in 15..31 {
+            if size <= 1 << i {
+                return Ok(1 << i);
+            }
+        }
+
+        // Verify the requested size is not above the maximum allowed.
+        if size > MAX_MAP_SIZE {
+            return Err(Error::MmapTooLarge);
+        }
+
+        // If larger than 1GB then grow by 1GB at a time.
+        let mut sz = size;
+        let remainder = sz % MAX_MMAP_STEP;
+        if remainder > 0 {
+            sz += MAX_MMAP_STEP - remainder;
+        }
+
+        // Ensure that the mmap size is a multiple of the page size.
+        // This should always be true since we're incrementing in MBs.
+        if sz % self.page_size != 0 {
+            sz = (sz / self.page_size + 1) * self.page_size;
+        }
+
+        // If we've exceeded the max size then only grow up to the max size.
+        if sz > MAX_MAP_SIZE {
+            sz = MAX_MAP_SIZE;
+        }
+
+        Ok(sz)
+    }
+
+    // close releases the mmap and the database file.
+    // Blocks until the write transaction and all read transactions have closed.
+    pub fn close(&self) {
+        // Wait for the writer to finish and keep new writers out.
+        let mut locked = self.rwlock.lock().unwrap();
+        while *locked {
+            locked = self.rwcond.wait(locked).unwrap();
+        }
+
+        // Wait for the readers to finish and keep new readers out.
+        let mut txs = self.mmaplock.lock().unwrap();
+        while !txs.is_empty() {
+            txs = self.mmapcond.wait(txs).unwrap();
+        }
+
+        self.opened.store(false, Ordering::SeqCst);
+        let _ = self.munmap();
+        *self.file.lock().unwrap() = None;
+    }
+
+    // meta retrieves the current meta page reference.
+    pub fn meta(&self) -> Meta {
+        // We have to return the meta with the highest txid which doesn't fail
+        // validation. Otherwise, we can cause errors when in fact the database is
+        // in a consistent state. page_a holds the meta with the higher txid.
+        let mut page_a = self.page(0);
+        let mut page_b = self.page(1);
+        unsafe {
+            if (*page_b.meta()).txid > (*page_a.meta()).txid {
+                mem::swap(&mut page_a, &mut page_b);
+            }
+
+            // Use higher meta page if valid. Otherwise fallback to previous, if valid.
+            if (*page_a.meta()).validate().is_ok() {
+                return Meta::read(page_a);
+            } else if (*page_b.meta()).validate().is_ok() {
+                return Meta::read(page_b);
+            }
+        }
+
+        // This should never be reached, because both meta1 and meta0 were validated
+        // on mmap() and we do fsync() on every write.
+        panic!("bolt.DB.meta(): invalid meta pages");
+    }
+
+    // page retrieves a page reference from the mmap based on the current page size.
+    pub(crate) fn page<'a>(&'a self, id: pgid_t) -> &'a Page {
+        let pos = id as usize * self.page_size;
+        let datasz = self.datasz();
+        if pos + self.page_size > datasz {
+            panic!("page {} out of mmap range ({} bytes)", id, datasz);
+        }
+        unsafe { &*(self.data.load(Ordering::SeqCst).offset(pos as isize) as *const Page) }
+    }
+
+    // read_page_size returns the page size stored in the first valid meta page.
+    // The second meta page starts one page into the file, so it is looked for
+    // at every candidate page size from 1KB up to 16MB that fits in the file.
+    fn read_page_size(&self, size: u64) -> Result<Option<usize>, Error> {
+        let mut buf = vec![0u8; get_page_header_size() + mem::size_of::<meta::Meta>()];
+        let offsets = Some(0).into_iter().chain((0..15).map(|i| 1024u64 << i));
+        for pos in offsets {
+            if pos + buf.len() as u64 > size {
+                break;
+            }
+            self.read_at(&mut buf, pos)?;
+            let m = unsafe { &*self.page_in_buffer(&mut buf, 0).meta() };
+            if m.validate().is_ok() && (pos == 0 || m.page_size as u64 == pos) {
+                return Ok(Some(m.page_size as usize));
+            }
+        }
+        Ok(None)
+    }
+
+    // page_in_buffer retrieves a page reference from a given byte array based on the current page size.
+    pub(crate) fn page_in_buffer<'a>(&self, buf: &'a mut [u8], id: pgid_t) -> &'a mut Page {
+        let offset = id as usize * self.page_size;
+        unsafe { &mut *(buf[offset..].as_mut_ptr() as *mut Page) }
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        match *self.file.lock().unwrap() {
+            None => Err(Error::DatabaseNotOpen),
+            Some(ref f) => f.read_exact_at(buf, offset).map_err(Error::Io),
+        }
+    }
+
+    pub(crate) fn write_at(&self, buf: &[u8], offset: u64) -> Result<(), Error> {
+        match *self.file.lock().unwrap() {
+            None => Err(Error::DatabaseNotOpen),
+            Some(ref f) => f.write_all_at(buf, offset).map_err(Error::Io),
+        }
+    }
+
+    pub(crate) fn sync(&self) -> Result<(), Error> {
+        match *self.file.lock().unwrap() {
+            None => Err(Error::DatabaseNotOpen),
+            Some(ref f) => f.sync_data().map_err(Error::Io),
+        }
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use db::{DB, Options, MAX_MAP_SIZE};
+    use page::{Page, FREELIST_PAGE_FLAG, LEAF_PAGE_FLAG};
+    use errors::Error;
+    use meta;
+    use std::os::unix::fs::FileExt;
+    use std::env;
+    use std::io;
+    use std::fs;
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::panic;
+    use std::sync::Arc;
+    use std::thread;
+
+    extern crate rand;
+
+    // TempPath is the path of a temporary file that is removed when it's dropped,
+    // so a failing test doesn't leave the file behind.
+    pub struct TempPath(PathBuf);
+
+    impl Deref for TempPath {
+        type Target = PathBuf;
+
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempPath {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    pub fn tempfile() -> TempPath {
+        let mut path = env::temp_dir();
+        path.push(format!("bolt-{}.db", rand::random::<u64>()));
+        TempPath(path)
+    }
+
+    // TestDB is a database opened on a temporary file. The database is closed and
+    // the file removed when it's dropped.
+    pub struct TestDB {
+        pub db: Arc<DB>,
+        pub path: TempPath,
+    }
+
+    impl TestDB {
+        pub fn new() -> TestDB {
+            TestDB::open(Options::default())
+        }
+
+        pub fn open(options: Options) -> TestDB {
+            let path = tempfile();
+            let db = Arc::new(DB::open(&path, options).unwrap());
+            TestDB { db: db, path: path }
+        }
+
+        // reopen closes the database and opens the same file again.
+        pub fn reopen(&mut self) {
+            self.db.close();
+            self.db = Arc::new(DB::open(&self.path, Options::default()).unwrap());
+        }
+    }
+
+    impl Deref for TestDB {
+        type Target = Arc<DB>;
+
+        fn deref(&self) -> &Arc<DB> {
+            &self.db
+        }
+    }
+
+    #[test]
+    fn db_open_creates_file() {
+        let db = TestDB::new();
+        assert!(db.opened());
+        assert_eq!(fs::metadata(&db.path).unwrap().len(), 4 * 4096);
+
+        let meta = db.meta();
+        assert_eq!(meta.page_size, 4096);
+        assert_eq!(meta.freelist, 2);
+        assert_eq!(meta.root.root, 3);
+        assert_eq!(meta.pgid, 4);
+        assert_eq!(meta.txid, 1);
+    }
+
+    #[test]
+    fn db_reopen() {
+        let path = tempfile();
+        {
+            let options = Options { page_size: 8192, ..Options::default() };
+            let db = DB::open(&path, options).unwrap();
+            db.close();
+            assert!(!db.opened());
+        }
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.page_size, 8192);
+        assert_eq!(db.meta().root.root, 3);
+        assert_eq!(db.freelist.lock().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn db_mmap_pages() {
+        let db = TestDB::new();
+        assert_eq!(db.datasz(), 32 * 1024);
+        assert_eq!({ db.page(2).flags }, FREELIST_PAGE_FLAG);
+        assert_eq!({ db.page(3).flags }, LEAF_PAGE_FLAG);
+        assert_eq!({ db.page(3).count }, 0);
+
+        // Remapping to a larger size keeps the pages readable.
+        db.mmap(1 << 20).unwrap();
+        assert_eq!(db.datasz(), 1 << 20);
+        assert_eq!({ db.page(3).id }, 3);
+        assert_eq!(db.meta().root.root, 3);
+    }
+
+    // Overwrites the meta stored on page id of the file at path using f.
+    fn update_meta<F: Fn(&mut meta::Meta)>(path: &PathBuf, page_size: u64, id: u64, f: F) {
+        let file = fs::OpenOptions::new().read(true).write(true).open(path).unwrap();
+        let mut buf = vec![0u8; page_size as usize];
+        file.read_exact_at(&mut buf, id * page_size).unwrap();
+        unsafe {
+            let m = (*(buf.as_mut_ptr() as *mut Page)).meta() as *mut meta::Meta;
+            f(&mut *m);
+        }
+        file.write_all_at(&buf, id * page_size).unwrap();
+    }
+
+    #[test]
+    fn db_meta_checksum_written() {
+        let db = TestDB::new();
+        unsafe {
+            assert!((*db.page(0).meta()).validate().is_ok());
+            assert!((*db.page(1).meta()).validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn db_meta_fallback_on_checksum_error() {
+        let path = tempfile();
+        DB::open(&path, Options::default()).unwrap();
+
+        // Corrupt the newer meta page without updating its checksum.
+        update_meta(&path, 4096, 1, |m| m.pgid = 100);
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        unsafe {
+            assert_eq!((*db.page(1).meta()).validate(), Err(Error::Checksum));
+        }
+        assert_eq!(db.meta().txid, 0);
+        assert_eq!(db.meta().pgid, 4);
+    }
+
+    #[test]
+    fn db_open_first_meta_corrupted() {
+        let path = tempfile();
+        DB::open(&path, Options { page_size: 8192, ..Options::default() }).unwrap();
+
+        // Corrupt the first meta page; the page size is read from the second.
+        update_meta(&path, 8192, 0, |m| m.magic = 0);
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.page_size(), 8192);
+        assert_eq!(db.meta().txid, 1);
+    }
+
+    #[test]
+    fn db_open_invalid_file() {
+        let path = tempfile();
+        fs::write(&path, vec![42u8; 4 * 4096]).unwrap();
+        assert_eq!(DB::open(&path, Options::default()).err(), Some(Error::Invalid));
+    }
+
+    #[test]
+    fn db_open_short_file() {
+        let path = tempfile();
+        fs::write(&path, vec![42u8; 100]).unwrap();
+        assert_eq!(DB::open(&path, Options::default()).err(), Some(Error::Invalid));
+    }
+
+    #[test]
+    fn db_open_version_mismatch() {
+        let path = tempfile();
+        DB::open(&path, Options::default()).unwrap();
+        for id in 0..2 {
+            update_meta(&path, 4096, id, |m| {
+                m.version = 100;
+                m.checksum = m.sum64();
+            });
+        }
+        assert_eq!(DB::open(&path, Options::default()).err(), Some(Error::VersionMismatch));
+    }
+
+    #[test]
+    fn db_mmap_size() {
+        let db = DB::new();
+        assert_eq!(db.mmap_size(0).unwrap(), 32768);
+        assert_eq!(db.mmap_size(32768).unwrap(), 32768);
+        assert_eq!(db.mmap_size(32769).unwrap(), 65536);
+        assert_eq!(db.mmap_size(1 << 30).unwrap(), 1 << 30);
+        assert_eq!(db.mmap_size((1 << 30) + 1).unwrap(), 2 << 30);
+        assert!(db.mmap_size(MAX_MAP_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn db_update() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            assert!(tx.writable);
+            tx.root_bucket().node(3, None).borrow_mut().put(b"foo", b"foo", Some(&b"bar"[..]), 0, 0);
+            Ok(())
+        }).unwrap();
+        assert_eq!(db.meta().txid, 2);
+        let root = db.meta().root.root;
+        assert_eq!({ db.page(root).count }, 1);
+    }
+
+    #[test]
+    fn db_update_error_rolls_back() {
+        let db = TestDB::new();
+        let result = DB::update(&db, |tx| {
+            tx.root_bucket().node(3, None).borrow_mut().put(b"foo", b"foo", Some(&b"bar"[..]), 0, 0);
+            Err(Error::BucketNotFound)
+        });
+        assert_eq!(result, Err(Error::BucketNotFound));
+        assert_eq!(db.meta().txid, 1);
+        assert_eq!({ db.page(3).count }, 0);
+    }
+
+    #[test]
+    fn db_update_panic_rolls_back() {
+        let db = TestDB::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _ = DB::update(&db, |tx| {
+                tx.free(3);
+                panic!("omg");
+            });
+        }));
+        assert!(result.is_err());
+        assert_eq!(db.freelist.lock().unwrap().pending_count(), 0);
+
+        // The database is still usable after the panic.
+        DB::update(&db, |_| Ok(())).unwrap();
+        assert_eq!(db.meta().txid, 2);
+    }
+
+    #[test]
+    fn db_update_managed_commit() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            assert_eq!(tx.commit(), Err(Error::ManagedTxCommitNotAllowed));
+            assert_eq!(tx.rollback(), Err(Error::ManagedTxRollbackNotAllowed));
+            Ok(())
+        }).unwrap();
+        assert_eq!(db.meta().txid, 2);
+    }
+
+    #[test]
+    fn db_view() {
+        let db = TestDB::new();
+        DB::view(&db, |tx| {
+            assert!(!tx.writable);
+            assert_eq!(tx.rollback(), Err(Error::ManagedTxRollbackNotAllowed));
+            Ok(())
+        }).unwrap();
+        assert_eq!(DB::view(&db, |_| Err(Error::BucketNotFound)), Err(Error::BucketNotFound));
+    }
+
+    #[test]
+    fn db_concurrent_readers() {
+        let db = TestDB::new();
+
+        // A read transaction in another thread doesn't block the writer.
+        let reader = DB::begin(&db, false).unwrap();
+        let handles: Vec<_> = (0..4).map(|_| {
+            let db = Arc::clone(&db.db);
+            thread::spawn(move || {
+                DB::view(&db, |tx| {
+                    assert_eq!(tx.meta.borrow().root.root, 3);
+                    Ok(())
+                }).unwrap();
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        DB::update(&db, |_| Ok(())).unwrap();
+        assert_eq!(reader.meta.borrow().txid, 1);
+        reader.rollback().unwrap();
+        assert_eq!(db.meta().txid, 2);
+    }
+
+    #[test]
+    fn db_writers_serialized() {
+        let db = TestDB::new();
+        let handles: Vec<_> = (0..8).map(|_| {
+            let db = Arc::clone(&db.db);
+            thread::spawn(move || {
+                DB::update(&db, |tx| {
+                    // Only one writer can see the current meta at a time.
+                    assert_eq!(tx.meta.borrow().txid, tx.db().meta().txid + 1);
+                    Ok(())
+                }).unwrap();
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(db.meta().txid, 9);
+    }
+
+    #[test]
+    fn db_close_not_open() {
+        let db = TestDB::new();
+        db.close();
+        assert!(!db.opened());
+        assert_eq!(DB::begin(&db, false).err(), Some(Error::DatabaseNotOpen));
+        assert_eq!(DB::begin(&db, true).err(), Some(Error::DatabaseNotOpen));
+    }
+
+    #[test]
+    fn db_open_io_error() {
+        let path = tempfile().join("missing");
+        match DB::open(&path, Options::default()) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            _ => panic!("expected an io error"),
+        }
+    }
+
+    #[test]
+    fn db_release_waits_for_readers() {
+        // Map enough up front that the writers never have to wait on the reader to remap.
+        let db = TestDB::open(Options { initial_mmap_size: 1 << 20, ..Options::default() });
+
+        // The first commit frees the original root and freelist pages.
+        let reader = DB::begin(&db, false).unwrap();
+        DB::update(&db, |tx| {
+            tx.root_bucket().node(3, None).borrow_mut().put(b"foo", b"foo", Some(&b"bar"[..]), 0, 0);
+            Ok(())
+        }).unwrap();
+        assert_eq!(db.freelist.lock().unwrap().pending_count(), 2);
+
+        // They stay pending while a reader that can still see them is open.
+        DB::update(&db, |_| Ok(())).unwrap();
+        assert!(db.freelist.lock().unwrap().pending[&2].contains(&3));
+        assert!(!db.freelist.lock().unwrap().ids.contains(&3));
+
+        // Once the reader closes the next writer releases them.
+        reader.rollback().unwrap();
+        DB::update(&db, |_| Ok(())).unwrap();
+        assert!(!db.freelist.lock().unwrap().pending.contains_key(&2));
+        assert!(db.freelist.lock().unwrap().ids.contains(&3));
     }
 }
@@ -0,0 +1,182 @@
+// These errors can be returned when opening or calling methods on a DB.
+use std::error;
+use std::fmt;
+use std::io;
+use std::mem;
+use types::pgid_t;
+
+#[derive(Debug)]
+pub enum Error {
+    // Returned when a DB instance is accessed before it is opened or after it is closed.
+    DatabaseNotOpen,
+
+    // Returned when both meta pages on a database are invalid.
+    // This typically occurs when a file is not a bolt database.
+    Invalid,
+
+    // Returned when the data file was created with a different version of Bolt.
+    VersionMismatch,
+
+    // Returned when either meta page checksum does not match.
+    Checksum,
+
+    // Returned when the database would need to be mapped beyond the maximum size.
+    MmapTooLarge,
+
+    // Returned when performing a write operation on a read-only transaction.
+    TxNotWritable,
+
+    // Returned when committing or rolling back a transaction that has already
+    // been committed or rolled back.
+    TxClosed,
+
+    // Returned when commit or rollback is called on a transaction owned by
+    // DB::update or DB::view.
+    ManagedTxCommitNotAllowed,
+    ManagedTxRollbackNotAllowed,
+
+    // Returned when trying to access a bucket that has not been created yet.
+    BucketNotFound,
+
+    // Returned when creating a bucket that already exists.
+    BucketExists,
+
+    // Returned when creating a bucket with a blank name.
+    BucketNameRequired,
+
+    // Returned when inserting a zero-length key.
+    KeyRequired,
+
+    // Returned when inserting a key that is larger than MAX_KEY_SIZE.
+    KeyTooLarge,
+
+    // Returned when inserting a value that is larger than MAX_VALUE_SIZE.
+    ValueTooLarge,
+
+    // Returned when trying create or delete a bucket on an existing non-bucket
+    // key or when trying to create or delete a non-bucket key on an existing
+    // bucket key.
+    IncompatibleValue,
+
+    // Returned when reading, writing or mapping the data file fails.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DatabaseNotOpen => write!(f, "database not open"),
+            Error::Invalid => write!(f, "invalid database"),
+            Error::VersionMismatch => write!(f, "version mismatch"),
+            Error::Checksum => write!(f, "checksum error"),
+            Error::MmapTooLarge => write!(f, "mmap too large"),
+            Error::TxNotWritable => write!(f, "tx not writable"),
+            Error::TxClosed => write!(f, "tx closed"),
+            Error::ManagedTxCommitNotAllowed => write!(f, "managed tx commit not allowed"),
+            Error::ManagedTxRollbackNotAllowed => write!(f, "managed tx rollback not allowed"),
+            Error::BucketNotFound => write!(f, "bucket not found"),
+            Error::BucketExists => write!(f, "bucket already exists"),
+            Error::BucketNameRequired => write!(f, "bucket name required"),
+            Error::KeyRequired => write!(f, "key required"),
+            Error::KeyTooLarge => write!(f, "key too large"),
+            Error::ValueTooLarge => write!(f, "value too large"),
+            Error::IncompatibleValue => write!(f, "incompatible value"),
+            Error::Io(ref e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// io::Error can't be compared so two Io errors are equal if they are of the same kind.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            (&Error::Io(ref a), &Error::Io(ref b)) => a.kind() == b.kind(),
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+// CheckError describes a consistency problem found by Tx::check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckError {
+    // A page appears in the freelist more than once.
+    AlreadyFreed(pgid_t),
+
+    // The freelist page is at or above the high water mark.
+    FreelistOutOfBounds { pgid: pgid_t, high: pgid_t },
+
+    // A reachable page is at or above the high water mark.
+    OutOfBounds { pgid: pgid_t, high: pgid_t },
+
+    // A page is reachable from more than one place.
+    MultipleReferences(pgid_t),
+
+    // A page is reachable but also in the freelist.
+    ReachableFreed(pgid_t),
+
+    // A key isn't greater than the key before it in the same bucket.
+    KeyOutOfOrder { pgid: pgid_t, index: usize, key: Vec<u8> },
+
+    // A reachable page is neither a branch nor a leaf page.
+    InvalidType { pgid: pgid_t, typ: String },
+
+    // A page below the high water mark is neither reachable nor freed.
+    UnreachableUnfreed(pgid_t),
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheckError::AlreadyFreed(id) => write!(f, "page {}: already freed", id),
+            CheckError::FreelistOutOfBounds { pgid, high } => write!(f, "page {}: freelist out of bounds: {}", pgid, high),
+            CheckError::OutOfBounds { pgid, high } => write!(f, "page {}: out of bounds: {}", pgid, high),
+            CheckError::MultipleReferences(id) => write!(f, "page {}: multiple references", id),
+            CheckError::ReachableFreed(id) => write!(f, "page {}: reachable freed", id),
+            CheckError::KeyOutOfOrder { pgid, index, ref key } => write!(f, "page {}: key {} out of order: {:?}", pgid, index, key),
+            CheckError::InvalidType { pgid, ref typ } => write!(f, "page {}: invalid type: {}", pgid, typ),
+            CheckError::UnreachableUnfreed(id) => write!(f, "page {}: unreachable unfreed", id),
+        }
+    }
+}
+
+impl error::Error for CheckError {}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use std::error::Error as StdError;
+    use std::io;
+
+    #[test]
+    fn error_display() {
+        assert_eq!(Error::BucketNotFound.to_string(), "bucket not found");
+        assert_eq!(Error::TxClosed.to_string(), "tx closed");
+        let e = Error::from(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+        assert_eq!(e.to_string(), "io error: disk on fire");
+        assert!(e.source().is_some());
+        assert!(Error::Invalid.source().is_none());
+    }
+
+    #[test]
+    fn error_eq() {
+        assert_eq!(Error::KeyTooLarge, Error::KeyTooLarge);
+        assert!(Error::KeyTooLarge != Error::ValueTooLarge);
+        assert_eq!(Error::Io(io::Error::new(io::ErrorKind::NotFound, "a")), Error::Io(io::ErrorKind::NotFound.into()));
+        assert!(Error::Io(io::ErrorKind::NotFound.into()) != Error::Io(io::ErrorKind::Other.into()));
+    }
+}
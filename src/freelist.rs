@@ -1,7 +1,5 @@
 use types::{txid_t, pgid_t};
 use page::{Page, get_page_header_size, merge_pgids, merge_pgids_raw, FREELIST_PAGE_FLAG};
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::slice;
@@ -107,7 +105,7 @@ impl FreeList {
 
                 // Remove from the free cache
                 for i in 0 as pgid_t .. n as pgid_t {
-                    self.cache.remove(&i);
+                    self.cache.remove(&(initial + i));
                 }
 
                 initial
@@ -117,8 +115,8 @@ impl FreeList {
 
     // free releases a page and its overflow for a given transaction id.
     // If the page is already free then a panic will occur.
-    pub fn free(&mut self, txid: txid_t, p: Rc<RefCell<Page>>) {
-        let pgid = p.borrow().id;
+    pub fn free(&mut self, txid: txid_t, p: &Page) {
+        let pgid = p.id;
         if pgid <= 1 {
             panic!("cannot free page 0 or 1: {}", pgid);
         }
@@ -131,7 +129,7 @@ impl FreeList {
         match ids_option {
             None => panic!("pending should not be None"),
             Some(ids) => {
-                for id in pgid..pgid + 1 + p.borrow().overflow as pgid_t {
+                for id in pgid..pgid + 1 + p.overflow as pgid_t {
                     // Verify that page is not already free.
                     if self.cache.contains(&id) {
                         panic!("page {} already freed")
@@ -164,13 +162,12 @@ impl FreeList {
 
     // rollback removes the pages from a given pending tx.
     pub fn rollback(&mut self, txid: txid_t) {
-        // Remove page ids from cache.
-        for id in &self.pending[&txid] {
-            self.cache.remove(id);
+        // Remove page ids from cache and the pages from pending list.
+        if let Some(ids) = self.pending.remove(&txid) {
+            for id in &ids {
+                self.cache.remove(id);
+            }
         }
-
-        // Remove pages from pending list
-        self.pending.remove(&txid);
     }
 
     // freed returns whether a given page is in the free list
@@ -195,11 +192,10 @@ impl FreeList {
             self.ids.clear();
         } else {
             let pgid_ptr = &p.ptr as *const usize as *const pgid_t;
-            self.ids.reserve(count - idx);
-            let mut pgids_slice = unsafe {
+            let pgids_slice = unsafe {
                 slice::from_raw_parts(pgid_ptr.offset(idx as isize), count)
             };
-            self.ids.append(&mut pgids_slice.to_vec());
+            self.ids = pgids_slice.to_vec();
 
             // Make sure they're sorted.
             self.ids.sort();
@@ -292,8 +288,6 @@ impl FreeList {
 #[cfg(test)]
 mod tests {
     use freelist::FreeList;
-    use std::rc::Rc;
-    use std::cell::RefCell;
     use page::{Page, FREELIST_PAGE_FLAG};
     use std::collections::{HashMap, HashSet};
     use types::pgid_t;
@@ -304,60 +298,60 @@ mod tests {
     #[test]
     fn freelist_free() {
         let mut f = FreeList::new();
-        let page = Rc::new(RefCell::new(Page{
+        let page = Page{
             id: 12,
             flags: 0,
             count: 0,
             overflow: 0,
             ptr: 0,
-        }));
-        f.free(100, Rc::clone(&page));
+        };
+        f.free(100, &page);
         assert_eq!(f.pending[&100], vec![12]);
     }
 
     #[test]
     fn freelist_free_overflow() {
         let mut f = FreeList::new();
-        let page = Rc::new(RefCell::new(Page{
+        let page = Page{
             id: 12,
             flags: 0,
             count: 0,
             overflow: 3,
             ptr: 0,
-        }));
-        f.free(100, Rc::clone(&page));
+        };
+        f.free(100, &page);
         assert_eq!(f.pending[&100], vec![12,13,14,15]);
     }
 
     #[test]
     fn freelist_release() {
         let mut f = FreeList::new();
-        let page1 = Rc::new(RefCell::new(Page {
+        let page1 = Page {
             id: 12,
             flags: 0,
             count: 0,
             overflow: 1,
             ptr: 0,
-        }));
-        f.free(100, Rc::clone(&page1));
+        };
+        f.free(100, &page1);
 
-        let page2 = Rc::new(RefCell::new(Page {
+        let page2 = Page {
             id: 9,
             flags: 0,
             count: 0,
             overflow: 0,
             ptr: 0,
-        }));
-        f.free(100, Rc::clone(&page2));
+        };
+        f.free(100, &page2);
 
-        let page3 = Rc::new(RefCell::new(Page {
+        let page3 = Page {
             id: 39,
             flags: 0,
             count: 0,
             overflow: 0,
             ptr: 0,
-        }));
-        f.free(102, Rc::clone(&page3));
+        };
+        f.free(102, &page3);
 
         f.release(100);
         f.release(101);
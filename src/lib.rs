@@ -3,6 +3,7 @@
 #![feature(test)]
 
 extern crate test;
+extern crate libc;
 
 // Re-export core for use by macros
 #[doc(hidden)]
@@ -23,3 +24,10 @@ mod db;
 mod page;
 mod meta;
 mod freelist;
+mod errors;
+
+pub use db::{DB, Options};
+pub use tx::{Tx, TxHandle, TxStats};
+pub use bucket::{Bucket, BucketStats, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+pub use cursor::{Cursor, Iter, KeyRange};
+pub use errors::{CheckError, Error};
@@ -1,5 +1,8 @@
 use bucket::_Bucket;
 use types::{pgid_t, txid_t};
+use db::{MAGIC, VERSION};
+use errors::Error;
+use std::slice;
 
 #[repr(C, packed)]
 pub struct Meta {
@@ -12,4 +15,47 @@ pub struct Meta {
     pub pgid: pgid_t,
     pub txid: txid_t,
     pub checksum: u64,
-}
\ No newline at end of file
+}
+
+impl Meta {
+    // validate checks the marker bytes and version of the meta page to ensure it matches this binary.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.magic != MAGIC {
+            return Err(Error::Invalid);
+        } else if self.version != VERSION {
+            return Err(Error::VersionMismatch);
+        } else if self.checksum != self.sum64() {
+            return Err(Error::Checksum);
+        }
+        Ok(())
+    }
+
+    // sum64 generates the checksum for the meta, covering every field before the checksum itself.
+    pub fn sum64(&self) -> u64 {
+        let len = offset_of!(Meta, checksum) as usize;
+        let buf = unsafe { slice::from_raw_parts(self as *const Meta as *const u8, len) };
+        fnv64a(buf)
+    }
+}
+
+// fnv64a computes the 64-bit FNV-1a hash of buf.
+pub fn fnv64a(buf: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in buf {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use meta::fnv64a;
+
+    #[test]
+    fn fnv64a_known_values() {
+        assert_eq!(fnv64a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv64a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv64a(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -5,16 +5,17 @@ use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use page;
 use std::ptr;
-use std::str;
+use std::mem;
 use tx::Tx;
+use errors::Error;
 
 // Node represents an in-memory, deserialized page.
 pub struct Node<'a> {
-    pub bucket: Rc<RefCell<Bucket<'a>>>,
+    pub bucket: Rc<Bucket<'a>>,
     pub is_leaf: bool,
     pub unbalanced: bool,
     pub spilled: bool,
-    pub key: &'a str,
+    pub key: &'a [u8],
     pub pgid: pgid_t,
     pub parent: Option<Weak<RefCell<Node<'a>>>>,
     pub inodes: Vec<INode<'a>>,
@@ -23,13 +24,13 @@ pub struct Node<'a> {
 }
 
 impl<'a> Node<'a> {
-    pub fn new(b: Rc<RefCell<Bucket<'a>>>) -> Node<'a> {
+    pub fn new(b: Rc<Bucket<'a>>) -> Node<'a> {
         Node {
             bucket: b,
             is_leaf: false,
             unbalanced: false,
             spilled: false,
-            key: "",
+            key: &[],
             pgid: 0,
             parent: None,
             children: RefCell::new(vec![]),
@@ -54,7 +55,7 @@ impl<'a> Node<'a> {
         }
     }
 
-    pub fn min_keys(self) -> i32 {
+    pub fn min_keys(&self) -> i32 {
         if self.is_leaf {
             1
         } else {
@@ -101,7 +102,7 @@ impl<'a> Node<'a> {
         }
     }
 
-    pub fn append_child(&mut self, child: &Rc<RefCell<Node<'a>>>) {
+    pub fn append_child(&self, child: &Rc<RefCell<Node<'a>>>) {
         let mut children = self.children.borrow_mut();
         children.push(Rc::clone(child));
     }
@@ -114,13 +115,13 @@ impl<'a> Node<'a> {
         if self.is_leaf {
             panic!("invalid child_at{} on a leaf node", index);
         }
-        self.bucket.borrow_mut().node(
+        self.bucket.node(
             self.inodes[index].pgid,
             Some(self.to_rc_refcell_node()),
         )
     }
 
-    fn find_key_index(&self, key: &'a str) -> usize {
+    fn find_key_index(&self, key: &[u8]) -> usize {
         let r = self.inodes.binary_search_by(|ref inode| inode.key.cmp(key));
         match r {
             Ok(idx) => idx,
@@ -168,9 +169,9 @@ impl<'a> Node<'a> {
     /*
     pub fn put(
         &mut self,
-        old_key: &'a str,
-        new_key: &'a str,
-        value: &'a str,
+        old_key: &'a [u8],
+        new_key: &'a [u8],
+        value: &'a [u8],
         pgid: pgid_t,
         flags: u32,
     ) {
@@ -209,16 +210,14 @@ impl<'a> Node<'a> {
 
     pub fn put(
         &mut self,
-        old_key: &'a str,
-        new_key: &'a str,
-        value: Option<&'a str>,
+        old_key: &'a [u8],
+        new_key: &'a [u8],
+        value: Option<&'a [u8]>,
         pgid: pgid_t,
         flags: u32,
     ) {
-        let bucket_borrow = self.bucket.borrow();
-        let tx_borrow = bucket_borrow.tx.borrow();
-        let meta_pgid = tx_borrow.meta.pgid;
-        if pgid > meta_pgid {
+        let meta_pgid = self.bucket.tx().meta.borrow().pgid;
+        if pgid >= meta_pgid {
             panic!("pgid {} above high water mark {}", pgid, meta_pgid)
         } else if old_key.len() == 0 {
             panic!("put: zero-length old key")
@@ -247,7 +246,7 @@ impl<'a> Node<'a> {
         assert!(inode.key.len() > 0, "put: zero-length inode key");
     }
 
-    pub fn del(&mut self, key: &'a str) {
+    pub fn del(&mut self, key: &[u8]) {
         let r = self.inodes.binary_search_by(|ref inode| inode.key.cmp(key));
         // Exit if the key isn't found.
         if r.is_err() {
@@ -273,8 +272,8 @@ impl<'a> Node<'a> {
                     self.inodes.push(INode {
                         flags: (*elem).flags,
                         pgid: 0,
-                        key:  str::from_utf8((*elem).key()).unwrap(),
-                        value: Some(str::from_utf8((*elem).value()).unwrap()),
+                        key: (*elem).key(),
+                        value: Some((*elem).value()),
                     });
                 }
             } else {
@@ -283,7 +282,7 @@ impl<'a> Node<'a> {
                     self.inodes.push(INode {
                         flags: 0,
                         pgid: (*elem).pgid,
-                        key: str::from_utf8((*elem).key()).unwrap(),
+                        key: (*elem).key(),
                         value: None,
                     })
                 }
@@ -296,7 +295,7 @@ impl<'a> Node<'a> {
             self.key = self.inodes[0].key;
             assert!(self.key.len() > 0, "read: zero-length node key")
         } else {
-            self.key = "";
+            self.key = &[];
         }
     }
 
@@ -332,7 +331,7 @@ impl<'a> Node<'a> {
                 let elem = p.leaf_page_element(i as u16)
                     as *mut page::LeafPageElement;
                 unsafe {
-                    (*elem).pos = b as u32 - elem as u32;
+                    (*elem).pos = (b as usize - elem as usize) as u32;
                     (*elem).flags = item.flags;
                     (*elem).ksize = item.key.len() as u32;
                     (*elem).vsize = match item.value {
@@ -344,7 +343,7 @@ impl<'a> Node<'a> {
                 let elem = p.branch_page_element(i as u16)
                     as *mut page::BranchPageElement;
                 unsafe {
-                    (*elem).pos = b as u32 - elem as u32;
+                    (*elem).pos = (b as usize - elem as usize) as u32;
                     (*elem).ksize = item.key.len() as u32;
                     (*elem).pgid = item.pgid;
                     assert_ne!((*elem).pgid, p.id, "write: circular dependency occurred");
@@ -377,81 +376,6 @@ impl<'a> Node<'a> {
         // DEBUG ONLY: n.dump()
     }
 
-    pub fn write_refcell(&mut self, p: Rc<RefCell<page::Page>>) {
-        // Initialize page
-        if self.is_leaf {
-            p.borrow_mut().flags |= page::LEAF_PAGE_FLAG;
-        } else {
-            p.borrow_mut().flags |= page::BRANCH_PAGE_FLAG;
-        }
-
-        if self.inodes.len() >= 0xFFFF {
-            panic!("inode overflow: {} (pgid={})", self.inodes.len(), p.borrow().id);
-        }
-        p.borrow_mut().count = self.inodes.len() as u16;
-
-        // Stop here if there are no items to write
-        if p.borrow().count == 0 {
-            return
-        }
-
-        // Loop over each item and write it to the page.
-        let mut b: *mut u8 = &mut p.borrow_mut().ptr as *mut usize as *mut u8;
-        unsafe {
-            b = b.offset(self.inodes.len() as isize * self.page_element_size() as isize);
-        }
-
-        for (i, item) in self.inodes.iter().enumerate() {
-            assert!(item.key.len() > 0, "write: zero-length inode key");
-
-            // Write the page element
-            if self.is_leaf {
-                let elem = p.borrow().leaf_page_element(i as u16)
-                    as *mut page::LeafPageElement;
-                unsafe {
-                    (*elem).pos = b as u32 - elem as u32;
-                    (*elem).flags = item.flags;
-                    (*elem).ksize = item.key.len() as u32;
-                    (*elem).vsize = match item.value {
-                        None => 0,
-                        Some(ref v) => v.len(),
-                    } as u32;
-                }
-            } else {
-                let elem = p.borrow().branch_page_element(i as u16)
-                    as *mut page::BranchPageElement;
-                unsafe {
-                    (*elem).pos = b as u32 - elem as u32;
-                    (*elem).ksize = item.key.len() as u32;
-                    (*elem).pgid = item.pgid;
-                    assert_ne!((*elem).pgid, p.borrow().id, "write: circular dependency occurred");
-                }
-            }
-
-            // If the length of key+value is larger than the max allocation size
-            // then we need to reallocate the byte array pointer.
-            //
-            // See: https://github.com/boltdb/bolt/pull/335
-            // Write data for the element to the end of the page.
-            let klen = item.key.len();
-            unsafe {
-                ptr::copy(item.key.as_ptr(), b, klen);
-                b = b.offset(klen as isize);
-            }
-            match item.value {
-                None => (),
-                Some(ref v) => {
-                    let vlen = v.len();
-                    unsafe {
-                        ptr::copy(v.as_ptr(), b, vlen);
-                        b = b.offset(vlen as isize);
-                    }
-                },
-            };
-        }
-        // DEBUG ONLY: n.dump()
-    }
-
     // split breaks up a node into multiple smaller nodes, if appropriate.
     // This should only be called from the spill() function.
     fn split(&mut self, page_size: usize, new_parents: &mut Vec<Rc<RefCell<Node<'a>>>>) -> Vec<Rc<RefCell<Node<'a>>>> {
@@ -491,7 +415,7 @@ impl<'a> Node<'a> {
         }
 
         // Determine the threshold before starting a new node
-        let mut fill_percent = self.bucket.borrow().fill_percent;
+        let mut fill_percent = self.bucket.fill_percent.get();
         if fill_percent < bucket::MIN_FILL_PERCENT {
             fill_percent = bucket::MIN_FILL_PERCENT;
         } else if fill_percent > bucket::MAX_FILL_PERCENT {
@@ -506,9 +430,8 @@ impl<'a> Node<'a> {
         // If there's no parent then we'll need to create one.
         if self.parent.is_none() {
             let node =  Rc::new(RefCell::new(Node::new(Rc::clone(&self.bucket))));
-            let node_refmut = node.borrow_mut();
-            let mut children = node_refmut.children.borrow_mut();
-            children.push(Rc::clone(&self.to_rc_refcell_node()));
+            node.borrow_mut().weak_self = Rc::downgrade(&node);
+            node.borrow().append_child(&self.to_rc_refcell_node());
             self.parent = Some(Rc::downgrade(&node));
             new_parents.push(Rc::clone(&node));
         }
@@ -518,7 +441,7 @@ impl<'a> Node<'a> {
             is_leaf: self.is_leaf,
             unbalanced: false,
             spilled: false,
-            key: "",
+            key: &[],
             pgid: 0,
             parent: None,
             inodes: self.inodes.split_off(split_index), // Split inodes across two nodes.
@@ -531,17 +454,13 @@ impl<'a> Node<'a> {
             None => panic!("node should have parent"),
             Some(ref p) => {
                 next.borrow_mut().parent = Some(Weak::clone(p));
-                let p_refmut_strong = p.upgrade().unwrap();
-                let p_refmut = p_refmut_strong.borrow_mut();
-                let mut children_refmut = p_refmut.children.borrow_mut();
-                children_refmut.push(Rc::clone(&next));
+                let p_strong = p.upgrade().unwrap();
+                p_strong.borrow().append_child(&next);
             },
         }
 
         // Update the statistics.
-        let bucket_borrow = self.bucket.borrow_mut();
-        let mut tx_borrow = bucket_borrow.tx.borrow_mut();
-        tx_borrow.stats.split += 1;
+        self.bucket.tx().stats.borrow_mut().split += 1;
 
         return Some(Rc::clone(&next))
     }
@@ -575,129 +494,291 @@ impl<'a> Node<'a> {
     }
 
     // spill writes the nodes to dirty pages and splits nodes as it goes.
-    // Returns an error if dirty pages cannot be allocated.
-    fn spill(&mut self) -> Result<Vec<Rc<RefCell<Node<'a>>>>, &'static str> {
+    // Returns an error if dirty pages cannot be allocated. Parents created by
+    // splitting a root node are returned so the caller can keep them alive.
+    pub fn spill(node: &Rc<RefCell<Node<'a>>>) -> Result<Vec<Rc<RefCell<Node<'a>>>>, Error> {
         let mut new_parents = Vec::new();
-        if self.spilled {
+        if node.borrow().spilled {
             return Ok(new_parents);
         }
 
         // Spill child nodes first. Child nodes can materialize sibling nodes in
         // the case of split-merge so we cannot use a range loop. We have to check
         // the children size on every loop iteration.
-        {
-            let mut children = self.children.borrow_mut();
-            children.sort_by_key(|node_strong| {
-                let node = node_strong.borrow();
-                let key = node.inodes[0].key;
-                key
-            });
-            for child in children.as_slice() {
-                let mut cnode = child.borrow_mut();
-                let result = cnode.spill();
-                if result.is_err() {
-                    return result;
+        node.borrow().children.borrow_mut().sort_by(|a, b| {
+            a.borrow().inodes[0].key.cmp(b.borrow().inodes[0].key)
+        });
+        let mut i = 0;
+        loop {
+            let child = {
+                let n = node.borrow();
+                let children = n.children.borrow();
+                if i >= children.len() {
+                    break;
                 }
-            }
-
-            // We no longer need the child list because it's only used for spill tracking.
-            children.clear();
+                Rc::clone(&children[i])
+            };
+            Node::spill(&child)?;
+            i += 1;
         }
 
-        let page_size = self.get_page_size();
+        // We no longer need the child list because it's only used for spill tracking.
+        node.borrow().children.borrow_mut().clear();
+
+        // Split nodes into appropriate sizes. The first node will always be n.
+        let tx = node.borrow().get_tx();
+        let page_size = tx.get_page_size();
+        let mut nodes = vec![Rc::clone(node)];
+        nodes.append(&mut node.borrow_mut().split(page_size, &mut new_parents));
 
-        // Split nodes into appropriate sizes. The first node will always be self.
-        let tx = self.get_tx();
-        let nodes = self.split(page_size, &mut new_parents);
-        for node in &nodes {
+        for n in &nodes {
             // Add node's page to the freelist if it's not new.
-            if node.borrow().pgid > 0 {
-                tx.borrow_mut().free(node.borrow().pgid);
-                node.borrow_mut().pgid = 0;
+            let pgid = n.borrow().pgid;
+            if pgid > 0 {
+                tx.free(pgid);
+                n.borrow_mut().pgid = 0;
             }
 
             // Allocate contiguous space for the node.
-            let result = tx.borrow_mut().allocate(self.size() / tx.borrow().get_page_size() + 1);
-            match result {
-                Err(s) => return Err(s),
-                Ok(p) => {
-                    // Write the code.
-                    if p.borrow().id >= tx.borrow().meta.pgid {
-                        panic!("pgid {} above high water mark {}", p.borrow().id, tx.borrow().meta.pgid);
-                    }
-                    self.pgid = p.borrow().id;
-                    self.write_refcell(Rc::clone(&p));
-                    self.spilled = true;
-
-                    // Insert into parent inodes.
-                    match self.parent {
-                        None => (),
-                        Some(ref p) => {
-                            let mut key = self.key;
-                            if key.len() == 0 {
-                                key = self.inodes[0].key;
-                            }
+            let count = n.borrow().size() / page_size + 1;
+            let p = tx.allocate(count)?;
 
-                            // 只有在spill的时候才会更新父节点的key
-                            let p_strong = p.upgrade().unwrap();
-                            p_strong.borrow_mut().put(key, self.inodes[0].key, None, self.pgid, 0);
-                            self.key = self.inodes[0].key;
-                            assert!(self.key.len() > 0, "spill: zero-length node key");
-                        }
-                    };
-                },
+            // Write the node.
+            let id = p.id;
+            if id >= tx.meta.borrow().pgid {
+                panic!("pgid {} above high water mark {}", id, tx.meta.borrow().pgid);
+            }
+            {
+                let mut n = n.borrow_mut();
+                n.pgid = id;
+                n.write(p);
+                n.spilled = true;
+            }
+
+            // Insert into parent inodes.
+            let parent = match n.borrow().parent {
+                None => None,
+                Some(ref p) => p.upgrade(),
+            };
+            if let Some(parent) = parent {
+                let (key, first_key, pgid) = {
+                    let n = n.borrow();
+                    let key = if n.key.len() == 0 { n.inodes[0].key } else { n.key };
+                    (key, n.inodes[0].key, n.pgid)
+                };
+
+                parent.borrow_mut().put(key, first_key, None, pgid, 0);
+                n.borrow_mut().key = first_key;
+                assert!(first_key.len() > 0, "spill: zero-length node key");
             }
 
             // Update the statistics
-            tx.borrow_mut().stats.spill += 1;
+            tx.stats.borrow_mut().spill += 1;
         }
 
         // If the root node split and created a new root then we need to spill that
         // as well. We'll clear out the children to make sure it doesn't try to respill.
-        if let Some(ref p) = self.parent {
-            let p_strong = p.upgrade().unwrap();
-            if p_strong.borrow().pgid == 0 {
-                self.children.borrow_mut().clear();
-                return p_strong.borrow_mut().spill();
+        let parent = match node.borrow().parent {
+            None => None,
+            Some(ref p) => p.upgrade(),
+        };
+        if let Some(parent) = parent {
+            if parent.borrow().pgid == 0 {
+                node.borrow().children.borrow_mut().clear();
+                new_parents.append(&mut Node::spill(&parent)?);
             }
         }
 
         Ok(new_parents)
     }
 
-    fn get_page_size(&self) -> usize {
-        let bucket_borrow = self.bucket.borrow();
-        let tx_borrow = bucket_borrow.tx.borrow();
-        let db_borrow = tx_borrow.db.borrow();
-        db_borrow.page_size
-    }
-
-    fn get_tx(&self) -> Rc<RefCell<Tx>> {
-        let bucket_borrow = self.bucket.borrow();
-        Rc::clone(&bucket_borrow.tx)
+    fn get_tx(&self) -> &'a Tx {
+        self.bucket.tx()
     }
 
     // attempts to combine the node with sibling nodes if the node fill
     // size is below a threshold or if there are not enough keys.
-    fn reblance(&mut self) {
-        unimplemented!();
+    pub fn reblance(node: &Rc<RefCell<Node<'a>>>) {
+        {
+            let mut n = node.borrow_mut();
+            if !n.unbalanced {
+                return;
+            }
+            n.unbalanced = false;
+        }
+
+        // Update statistics.
+        let bucket = Rc::clone(&node.borrow().bucket);
+        let tx = bucket.tx();
+        tx.stats.borrow_mut().rebalance += 1;
+
+        // Ignore if node is above threshold (25%) and has enough keys.
+        let threshold = tx.get_page_size() / 4;
+        {
+            let n = node.borrow();
+            if n.size() > threshold && n.inodes.len() > n.min_keys() as usize {
+                return;
+            }
+        }
+
+        // Root node has special handling.
+        let parent = match node.borrow().parent {
+            Some(ref p) => p.upgrade(),
+            None => None,
+        };
+        let parent = match parent {
+            Some(p) => p,
+            None => {
+                // If root node is a branch and only has one node then collapse it.
+                let collapse = {
+                    let n = node.borrow();
+                    !n.is_leaf && n.inodes.len() == 1
+                };
+                if collapse {
+                    // Move root's child up.
+                    let child_pgid = node.borrow().inodes[0].pgid;
+                    let child = bucket.node(child_pgid, Some(Rc::clone(node)));
+                    {
+                        let mut n = node.borrow_mut();
+                        let mut c = child.borrow_mut();
+                        n.is_leaf = c.is_leaf;
+                        n.inodes = mem::replace(&mut c.inodes, Vec::new());
+                        *n.children.borrow_mut() = mem::replace(&mut *c.children.borrow_mut(), Vec::new());
+                    }
+
+                    // Reparent all child nodes being moved.
+                    if !node.borrow().is_leaf {
+                        let nodes = bucket.nodes.borrow();
+                        for inode in &node.borrow().inodes {
+                            if let Some(child) = nodes.get(&{ inode.pgid }) {
+                                child.borrow_mut().parent = Some(Rc::downgrade(node));
+                            }
+                        }
+                    }
+
+                    // Remove old child.
+                    child.borrow_mut().parent = None;
+                    bucket.nodes.borrow_mut().remove(&child_pgid);
+                    child.borrow_mut().free();
+                }
+                return;
+            },
+        };
+
+        // If node has no keys then just remove it.
+        if node.borrow().num_children() == 0 {
+            let (key, pgid) = {
+                let n = node.borrow();
+                (n.key, n.pgid)
+            };
+            parent.borrow_mut().del(key);
+            parent.borrow().remove_child(node);
+            bucket.nodes.borrow_mut().remove(&pgid);
+            node.borrow_mut().free();
+            Node::reblance(&parent);
+            return;
+        }
+
+        assert!(parent.borrow().num_children() > 1, "parent must have at least 2 children");
+
+        // Destination node is right sibling if idx == 0, otherwise left sibling.
+        let use_next_sibling = parent.borrow().child_index(&node.borrow()) == 0;
+        let target = if use_next_sibling {
+            node.borrow().next_sibling()
+        } else {
+            node.borrow().prev_sibling()
+        };
+        let target = target.unwrap();
+
+        // If both this node and the target node are too small then merge them.
+        // Everything is moved into whichever of the two is on the left.
+        let (from, to) = if use_next_sibling {
+            (Rc::clone(&target), Rc::clone(node))
+        } else {
+            (Rc::clone(node), Rc::clone(&target))
+        };
+
+        // Reparent all child nodes being moved.
+        Node::reparent_children(&from, &to);
+
+        // Copy over inodes and remove the node they were taken from.
+        let inodes = mem::replace(&mut from.borrow_mut().inodes, Vec::new());
+        to.borrow_mut().inodes.extend(inodes);
+        let (key, pgid) = {
+            let n = from.borrow();
+            (n.key, n.pgid)
+        };
+        parent.borrow_mut().del(key);
+        parent.borrow().remove_child(&from);
+        bucket.nodes.borrow_mut().remove(&pgid);
+        from.borrow_mut().free();
+
+        // Either this node or the target node was deleted from the parent so rebalance it.
+        Node::reblance(&parent);
+    }
+
+    // moves the materialized children of a branch node that is being merged
+    // away under the node it is merged into.
+    fn reparent_children(from: &Rc<RefCell<Node<'a>>>, to: &Rc<RefCell<Node<'a>>>) {
+        let from = from.borrow();
+        if from.is_leaf {
+            return;
+        }
+        let nodes = from.bucket.nodes.borrow();
+        for inode in &from.inodes {
+            if let Some(child) = nodes.get(&{ inode.pgid }) {
+                let old_parent = match child.borrow().parent {
+                    Some(ref p) => p.upgrade(),
+                    None => None,
+                };
+                if let Some(p) = old_parent {
+                    p.borrow().remove_child(child);
+                }
+                child.borrow_mut().parent = Some(Rc::downgrade(to));
+                to.borrow().append_child(child);
+            }
+        }
     }
 
     // remove a node from the list of in-memory children.
     // This does not affect the inodes.
-    fn remove_child(&mut self, target: Rc<RefCell<Node<'a>>>) {
-        unimplemented!();
+    fn remove_child(&self, target: &Rc<RefCell<Node<'a>>>) {
+        self.children.borrow_mut().retain(|child| !Rc::ptr_eq(child, target));
     }
 
     // dereference causes the node to copy all its inode key/value references to heap memory.
     // This is required when the mmap is reallocated so inodes are not pointing to stale data.
-    fn dereference(&mut self) {
-        unimplemented!();
+    pub fn dereference(&mut self) {
+        let tx = self.bucket.tx();
+        if self.key.len() > 0 {
+            self.key = tx.copy_bytes(self.key);
+        }
+
+        for inode in &mut self.inodes {
+            inode.key = tx.copy_bytes(inode.key);
+            assert!(inode.key.len() > 0, "dereference: zero-length inode key");
+
+            inode.value = match inode.value {
+                None => None,
+                Some(v) => Some(tx.copy_bytes(v)),
+            };
+        }
+
+        // Recursively dereference children.
+        for child in self.children.borrow().iter() {
+            child.borrow_mut().dereference();
+        }
+
+        // Update statistics.
+        tx.stats.borrow_mut().node_deref += 1;
     }
 
     // free adds the node's underlying page to the freelist.
-    fn free(&mut self) {
-        unimplemented!();
+    pub fn free(&mut self) {
+        if self.pgid != 0 {
+            self.bucket.tx().free(self.pgid);
+            self.pgid = 0;
+        }
     }
 }
 
@@ -708,8 +789,8 @@ impl<'a> Node<'a> {
 pub struct INode<'a> {
     pub flags: u32,
     pub pgid: pgid_t,
-    pub key: &'a str,
-    pub value: Option<&'a str>,
+    pub key: &'a [u8],
+    pub value: Option<&'a [u8]>,
 }
 
 impl<'a> INode<'a> {
@@ -717,7 +798,7 @@ impl<'a> INode<'a> {
         INode {
             flags: 0,
             pgid: 0,
-            key: "",
+            key: &[],
             value: None,
         }
     }
@@ -728,6 +809,7 @@ mod tests {
     use node::Node;
     use std::rc::Rc;
     use std::cell::RefCell;
+    use std::sync::Arc;
     use bucket::{Bucket, _Bucket};
     use tx::Tx;
     use std::str;
@@ -737,39 +819,41 @@ mod tests {
 
     #[test]
     fn node_put() {
-        let db = Rc::new(RefCell::new(DB::new()));
-        let bucket: Rc<RefCell<Bucket>> = Rc::new(RefCell::new(Bucket::new(
-            Box::new(_Bucket {
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        tx.meta.borrow_mut().pgid = 1;
+        let bucket = Rc::new(Bucket::new(
+            _Bucket {
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(Tx::new(&db))),
-        )));
+            },
+            &tx,
+        ));
         let mut node = Node::new(Rc::clone(&bucket));
-        node.put("baz", "baz", Some("2"), 0, 0);
-        node.put("foo", "foo", Some("0"), 0, 0);
-        node.put("bar", "bar", Some("1"), 0, 0);
-        node.put("foo", "foo", Some("3"), 0, 0x02);
+        node.put(b"baz", b"baz", Some(&b"2"[..]), 0, 0);
+        node.put(b"foo", b"foo", Some(&b"0"[..]), 0, 0);
+        node.put(b"bar", b"bar", Some(&b"1"[..]), 0, 0);
+        node.put(b"foo", b"foo", Some(&b"3"[..]), 0, 0x02);
 
         assert_eq!(node.inodes.len(), 3);
         assert_eq!(node.size(), 16 + 3 * (16 + 4));
 
         {
             let inode = &node.inodes[0];
-            assert_eq!(inode.key, "bar");
-            assert_eq!(inode.value, Some("1"));
+            assert_eq!(inode.key, b"bar");
+            assert_eq!(inode.value, Some(&b"1"[..]));
         }
 
         {
             let inode = &node.inodes[1];
-            assert_eq!(inode.key, "baz");
-            assert_eq!(inode.value, Some("2"));
+            assert_eq!(inode.key, b"baz");
+            assert_eq!(inode.value, Some(&b"2"[..]));
         }
 
         {
             let inode = &node.inodes[2];
-            assert_eq!(inode.key, "foo");
-            assert_eq!(inode.value, Some("3"));
+            assert_eq!(inode.key, b"foo");
+            assert_eq!(inode.value, Some(&b"3"[..]));
         }
 
         {
@@ -816,44 +900,45 @@ mod tests {
         }
 
         // Deserialize page into a leaf.
-        let db = Rc::new(RefCell::new(DB::new()));
-        let mut n = Node::new(Rc::new(RefCell::new(Bucket::new(
-            Box::new(_Bucket {
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        let mut n = Node::new(Rc::new(Bucket::new(
+            _Bucket {
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(Tx::new(&db))),
-        ))));
+            },
+            &tx,
+        )));
         unsafe { n.read(page.as_mut().unwrap()); }
 
         // Check that there are two inodes with correct data.
         assert!(n.is_leaf, "expected leaf");
         assert_eq!(n.inodes.len(), 2);
-        assert_eq!(n.inodes[0].key, "bar");
-        assert_eq!(n.inodes[0].value, Some("fooz"));
-        assert_eq!(n.inodes[1].key, "helloworld");
-        assert_eq!(n.inodes[1].value, Some("bye"));
+        assert_eq!(n.inodes[0].key, b"bar");
+        assert_eq!(n.inodes[0].value, Some(&b"fooz"[..]));
+        assert_eq!(n.inodes[1].key, b"helloworld");
+        assert_eq!(n.inodes[1].value, Some(&b"bye"[..]));
     }
 
     #[test]
     fn node_write_leaf_page() {
-        let db = Rc::new(RefCell::new(DB::new()));
-        let mut tx = Tx::new(&db);
-        tx.meta.pgid = 1;
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        tx.meta.borrow_mut().pgid = 1;
 
         let bucket = Bucket::new(
-            Box::new(_Bucket{
+            _Bucket{
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(tx)),
+            },
+            &tx,
         );
 
-        let mut n = Node::new(Rc::new(RefCell::new(bucket)));
+        let mut n = Node::new(Rc::new(bucket));
         n.is_leaf = true;
-        n.put("susy", "susy", Some("que"), 0, 0);
-        n.put("ricki", "ricki", Some("lake"), 0, 0);
-        n.put("john", "john", Some("johnson"), 0, 0);
+        n.put(b"susy", b"susy", Some(&b"que"[..]), 0, 0);
+        n.put(b"ricki", b"ricki", Some(&b"lake"[..]), 0, 0);
+        n.put(b"john", b"john", Some(&b"johnson"[..]), 0, 0);
 
         // write it to a page
         let mut buf: [u8; 4096] = [0; 4096];
@@ -862,51 +947,51 @@ mod tests {
         unsafe { n.write(page.as_mut().unwrap()); };
 
         // Read the page back in
-        let mut n2 = Node::new(Rc::new(RefCell::new(Bucket::new(
-            Box::new(_Bucket {
+        let mut n2 = Node::new(Rc::new(Bucket::new(
+            _Bucket {
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(Tx::new(&db))),
-        ))));
+            },
+            &tx,
+        )));
         unsafe { n2.read(page.as_mut().unwrap()); }
 
         // Check that the two pages are the same.
         assert_eq!(n2.inodes.len(), 3);
 
-        assert_eq!(n2.inodes[0].key, "john");
-        assert_eq!(n2.inodes[0].value, Some("johnson"));
+        assert_eq!(n2.inodes[0].key, b"john");
+        assert_eq!(n2.inodes[0].value, Some(&b"johnson"[..]));
 
-        assert_eq!(n2.inodes[1].key, "ricki");
-        assert_eq!(n2.inodes[1].value, Some("lake"));
+        assert_eq!(n2.inodes[1].key, b"ricki");
+        assert_eq!(n2.inodes[1].value, Some(&b"lake"[..]));
 
-        assert_eq!(n2.inodes[2].key, "susy");
-        assert_eq!(n2.inodes[2].value, Some("que"));
+        assert_eq!(n2.inodes[2].key, b"susy");
+        assert_eq!(n2.inodes[2].value, Some(&b"que"[..]));
     }
 
     // Ensure that a node can split into approriate subgroups.
     #[test]
     fn node_split() {
         // Create a node
-        let db = Rc::new(RefCell::new(DB::new()));
-        let mut tx = Tx::new(&db);
-        tx.meta.pgid = 1;
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        tx.meta.borrow_mut().pgid = 1;
 
         let bucket = Bucket::new(
-            Box::new(_Bucket{
+            _Bucket{
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(tx)),
+            },
+            &tx,
         );
 
-        let n = Rc::new(RefCell::new(Node::new(Rc::new(RefCell::new(bucket)))));
+        let n = Rc::new(RefCell::new(Node::new(Rc::new(bucket))));
         n.borrow_mut().weak_self = Rc::downgrade(&n);
-        n.borrow_mut().put("00000001", "00000001", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000002", "00000002", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000003", "00000003", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000004", "00000004", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000005", "00000005", Some("0123456701234567"), 0, 0);
+        n.borrow_mut().put(b"00000001", b"00000001", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000002", b"00000002", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000003", b"00000003", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000004", b"00000004", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000005", b"00000005", Some(&b"0123456701234567"[..]), 0, 0);
 
         // Split between 2 & 3
         let mut new_parents = vec![];
@@ -936,22 +1021,22 @@ mod tests {
     #[test]
     fn node_split_min_keys() {
         // Create a node
-        let db = Rc::new(RefCell::new(DB::new()));
-        let mut tx = Tx::new(&db);
-        tx.meta.pgid = 1;
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        tx.meta.borrow_mut().pgid = 1;
 
         let bucket = Bucket::new(
-            Box::new(_Bucket{
+            _Bucket{
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(tx)),
+            },
+            &tx,
         );
 
-        let n = Rc::new(RefCell::new(Node::new(Rc::new(RefCell::new(bucket)))));
+        let n = Rc::new(RefCell::new(Node::new(Rc::new(bucket))));
         n.borrow_mut().weak_self = Rc::downgrade(&n);
-        n.borrow_mut().put("00000001", "00000001", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000002", "00000002", Some("0123456701234567"), 0, 0);
+        n.borrow_mut().put(b"00000001", b"00000001", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000002", b"00000002", Some(&b"0123456701234567"[..]), 0, 0);
 
         // Split
         let mut new_parents = vec![];
@@ -964,25 +1049,25 @@ mod tests {
     #[test]
     fn node_split_single_page() {
         // Create a node
-        let db = Rc::new(RefCell::new(DB::new()));
-        let mut tx = Tx::new(&db);
-        tx.meta.pgid = 1;
+        let db = Arc::new(DB::new());
+        let tx = Rc::new(Tx::new(&db));
+        tx.meta.borrow_mut().pgid = 1;
 
         let bucket = Bucket::new(
-            Box::new(_Bucket{
+            _Bucket{
                 root: 0,
                 sequence: 0,
-            }),
-            &Rc::new(RefCell::new(tx)),
+            },
+            &tx,
         );
 
-        let n = Rc::new(RefCell::new(Node::new(Rc::new(RefCell::new(bucket)))));
+        let n = Rc::new(RefCell::new(Node::new(Rc::new(bucket))));
         n.borrow_mut().weak_self = Rc::downgrade(&n);
-        n.borrow_mut().put("00000001", "00000001", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000002", "00000002", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000003", "00000003", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000004", "00000004", Some("0123456701234567"), 0, 0);
-        n.borrow_mut().put("00000005", "00000005", Some("0123456701234567"), 0, 0);
+        n.borrow_mut().put(b"00000001", b"00000001", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000002", b"00000002", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000003", b"00000003", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000004", b"00000004", Some(&b"0123456701234567"[..]), 0, 0);
+        n.borrow_mut().put(b"00000005", b"00000005", Some(&b"0123456701234567"[..]), 0, 0);
 
         // Split between 2 & 3
         let mut new_parents = vec![];
@@ -1,52 +1,579 @@
 use db::{Meta, DB};
-use page::Page;
+use page::{Page, BRANCH_PAGE_FLAG, LEAF_PAGE_FLAG, BUCKET_LEAF_FLAG};
+use bucket::{Bucket, _Bucket};
+use freelist::FreeList;
 use types::pgid_t;
-use std::time::Duration;
-use std::ops::{Add, Sub, AddAssign, SubAssign};
+use errors::{CheckError, Error};
+use std::time::{Duration, Instant};
+use std::ops::{Add, Deref, Sub, AddAssign, SubAssign};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::mem;
+use std::ptr;
+use std::slice;
 
+// Tx represents a read-only or read/write transaction on the database.
+// Read-only transactions can be used for retrieving values for keys and creating cursors.
+// Read/write transactions can create and remove buckets and create and remove keys.
 pub struct Tx {
-    pub writable: bool,
-    pub meta: Meta,
-    pub stats: TxStats,
-    pub db: Rc<RefCell<DB>>
+    pub(crate) writable: bool,
+    pub(crate) managed: Cell<bool>, // set while the transaction is owned by DB::update or DB::view
+    pub(crate) locked: Cell<bool>,  // set while the transaction holds the writer lock or a reader slot
+    pub(crate) meta: RefCell<Meta>,
+    pub(crate) root: RefCell<Option<Rc<Bucket<'static>>>>,
+    pub(crate) pages: RefCell<HashMap<pgid_t, Vec<u8>>>, // dirty pages written in this transaction
+    pub(crate) arena: RefCell<Vec<Vec<u8>>>, // keys and values copied in by writes; freed with the transaction
+    pub(crate) stats: RefCell<TxStats>,
+    pub(crate) db: RefCell<Option<Arc<DB>>>, // None once the transaction is closed
 }
 
 impl Tx {
-    pub fn new(db: &Rc<RefCell<DB>>) -> Tx {
+    pub(crate) fn new(db: &Arc<DB>) -> Tx {
         Tx {
             writable: false,
-            meta: Meta::new(),
-            stats: TxStats::new(),
-            db: Rc::clone(db),
+            managed: Cell::new(false),
+            locked: Cell::new(false),
+            meta: RefCell::new(Meta::new()),
+            root: RefCell::new(None),
+            pages: RefCell::new(HashMap::new()),
+            arena: RefCell::new(Vec::new()),
+            stats: RefCell::new(TxStats::new()),
+            db: RefCell::new(Some(Arc::clone(db))),
         }
     }
 
+    // init initializes the transaction with a copy of the current meta page
+    // and a root bucket pointing at its root.
+    // The transaction must already be at its final address since the root bucket
+    // points back at it.
+    pub(crate) fn init(&self) {
+        let mut meta = self.db().meta();
+
+        // Increment the transaction id for writable transactions.
+        if self.writable {
+            meta.txid += 1;
+        }
+
+        // The root bucket is cached on the transaction for as long as it's open.
+        // It's only ever handed out through root_bucket, which ties it back to a
+        // borrow of the transaction.
+        let tx = unsafe { &*(self as *const Tx) };
+        let root = Bucket::new_rc(meta.root, tx);
+        *self.meta.borrow_mut() = meta;
+        *self.root.borrow_mut() = Some(root);
+    }
+
+    // db returns the database that created the transaction.
+    // Panics if the transaction has already been closed.
+    pub fn db(&self) -> Arc<DB> {
+        match *self.db.borrow() {
+            None => panic!("tx closed"),
+            Some(ref db) => Arc::clone(db),
+        }
+    }
+
+    // root_bucket returns the top level bucket of the transaction.
+    // The bucket, and every key and value read through it, borrows the transaction.
+    // TxHandle::commit and TxHandle::rollback consume the transaction so none of
+    // them can be used once it's closed.
+    pub fn root_bucket<'a>(&'a self) -> Rc<Bucket<'a>> {
+        match *self.root.borrow() {
+            None => panic!("tx closed"),
+            Some(ref b) => unsafe { mem::transmute::<Rc<Bucket<'static>>, Rc<Bucket<'a>>>(Rc::clone(b)) },
+        }
+    }
+
+    // copy_bytes copies data into memory owned by the transaction.
+    // The copy stays at the same address until the transaction is dropped.
+    pub(crate) fn copy_bytes<'a>(&'a self, data: &[u8]) -> &'a [u8] {
+        let buf = data.to_vec();
+        let copy = unsafe { slice::from_raw_parts(buf.as_ptr(), buf.len()) };
+        self.arena.borrow_mut().push(buf);
+        copy
+    }
+
     // page returns a reference to the page with a given id.
     // If page has been written to then a temporary buffered page is returned.
-    pub fn page(&self, pgid: pgid_t) -> Rc<RefCell<Page>> {
-        unimplemented!();
+    pub(crate) fn page<'a>(&'a self, pgid: pgid_t) -> &'a Page {
+        // Check the dirty pages first.
+        if let Some(buf) = self.pages.borrow().get(&pgid) {
+            return unsafe { &*(buf.as_ptr() as *const Page) };
+        }
+
+        // Otherwise return directly from the mmap, which the transaction keeps open.
+        let db = self.db();
+        unsafe { &*(db.page(pgid) as *const Page) }
+    }
+
+    // for_each_page iterates over every page within a given page and executes a function.
+    pub fn for_each_page<'a, F>(&'a self, pgid: pgid_t, depth: i64, f: &mut F)
+    where F: FnMut(&'a Page, i64) {
+        let p: &'a Page = self.page(pgid);
+
+        // Execute function.
+        f(p, depth);
+
+        // Recursively loop over children.
+        if (p.flags & BRANCH_PAGE_FLAG) != 0 {
+            for i in 0..p.count {
+                let pgid = unsafe { (*p.branch_page_element(i)).pgid };
+                self.for_each_page(pgid, depth + 1, f);
+            }
+        }
+    }
+
+    // size returns current database size in bytes as seen by this transaction.
+    pub fn size(&self) -> u64 {
+        self.meta.borrow().pgid * self.get_page_size() as u64
+    }
+
+    // write_to writes the entire database to a writer and returns the number of bytes written.
+    // Both meta pages are rewritten for this transaction so the copy opens at the
+    // snapshot it was taken from, followed by every page up to the high water mark.
+    // It should be called from a read transaction so writers can keep committing
+    // while the copy is streamed.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<u64, Error> {
+        if self.db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        }
+        let db = self.db();
+        let page_size = db.page_size;
+        let meta = self.meta.borrow().clone();
+
+        // Generate both meta pages. The other meta page gets the previous transaction
+        // id so this transaction's meta is the one picked up when the copy is opened.
+        let mut buf = vec![0u8; page_size * 2];
+        for &txid in [meta.txid, meta.txid.wrapping_sub(1)].iter() {
+            let mut m = meta.clone();
+            m.txid = txid;
+            m.write(db.page_in_buffer(&mut buf, txid % 2));
+        }
+        w.write_all(&buf)?;
+        let mut n = buf.len() as u64;
+
+        // Copy data pages straight out of the mmap. Open read transactions keep
+        // the mmap from being remapped underneath us.
+        for id in 2..meta.pgid {
+            let p = db.page(id) as *const Page as *const u8;
+            w.write_all(unsafe { slice::from_raw_parts(p, page_size) })?;
+            n += page_size as u64;
+        }
+        Ok(n)
+    }
+
+    // copy_file copies the entire database to file at the given path.
+    // A reader transaction is maintained during the copy so it is safe to continue
+    // using the database while a copy is in progress.
+    pub fn copy_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut f = File::create(path)?;
+        self.write_to(&mut f)?;
+        f.sync_all()?;
+        Ok(())
+    }
+
+    // check performs several consistency checks on the database for this transaction
+    // and passes every problem found to f as soon as it is found. The database is
+    // consistent if f is never called. A read-only transaction checks its own
+    // snapshot so writers can keep committing while the check runs.
+    //
+    // The following checks are made:
+    //   - every reachable page is below the high water mark
+    //   - no page is both reachable and freed, and no page is freed twice
+    //   - no page is reachable more than once
+    //   - keys are sorted within and across the pages of each bucket
+    //   - every page below the high water mark is either reachable or freed
+    pub fn check<F: FnMut(CheckError)>(&self, mut f: F) -> Result<(), Error> {
+        if self.db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        }
+        let db = self.db();
+        let (root, freelist_pgid, high) = {
+            let meta = self.meta.borrow();
+            (meta.root.root, meta.freelist, meta.pgid)
+        };
+
+        // Check if any pages are double freed. A writer sees the shared freelist
+        // with its own pending pages; a reader sees the freelist of its snapshot.
+        let all = if self.writable {
+            let freelist = db.freelist.lock().unwrap();
+            let mut all = vec![0; freelist.count()];
+            freelist.copyall(all.as_mut_ptr());
+            all
+        } else if freelist_pgid < high {
+            let mut freelist = FreeList::new();
+            freelist.read(self.page(freelist_pgid));
+            freelist.ids
+        } else {
+            vec![]
+        };
+        let mut freed = HashSet::new();
+        for id in all {
+            if !freed.insert(id) {
+                f(CheckError::AlreadyFreed(id));
+            }
+        }
+
+        // Track every reachable page.
+        let mut reachable = HashSet::new();
+        reachable.insert(0); // meta0
+        reachable.insert(1); // meta1
+        if freelist_pgid >= high {
+            f(CheckError::FreelistOutOfBounds { pgid: freelist_pgid, high: high });
+        } else {
+            for i in 0..=self.page(freelist_pgid).overflow as pgid_t {
+                reachable.insert(freelist_pgid + i);
+            }
+        }
+
+        // Recursively check buckets.
+        self.check_bucket(root, &mut reachable, &freed, &mut f);
+
+        // Ensure all pages below high water mark are either reachable or freed.
+        for i in 0..high {
+            if !reachable.contains(&i) && !freed.contains(&i) {
+                f(CheckError::UnreachableUnfreed(i));
+            }
+        }
+        Ok(())
+    }
+
+    // check_bucket checks every page of the bucket rooted at a given page and
+    // then every bucket nested within it.
+    fn check_bucket(&self, root: pgid_t, reachable: &mut HashSet<pgid_t>, freed: &HashSet<pgid_t>, f: &mut dyn FnMut(CheckError)) {
+        // Ignore inline buckets.
+        if root == 0 {
+            return;
+        }
+
+        let mut prev = None;
+        let mut children = vec![];
+        self.check_page(root, &mut prev, &mut children, reachable, freed, f);
+
+        // Check each bucket within this bucket.
+        for child in children {
+            self.check_bucket(child, reachable, freed, f);
+        }
+    }
+
+    // check_page checks a page and recursively its children. Leaf keys are
+    // visited in order so prev holds the last key seen in the bucket, and the
+    // roots of nested buckets are collected into children.
+    fn check_page<'a>(&'a self, pgid: pgid_t, prev: &mut Option<&'a [u8]>, children: &mut Vec<pgid_t>,
+                      reachable: &mut HashSet<pgid_t>, freed: &HashSet<pgid_t>, f: &mut dyn FnMut(CheckError)) {
+        let high = self.meta.borrow().pgid;
+        if pgid >= high {
+            f(CheckError::OutOfBounds { pgid: pgid, high: high });
+            return;
+        }
+        let p: &'a Page = self.page(pgid);
+
+        // Ensure each page is only referenced once. A page that was already
+        // visited isn't descended into again so a cycle can't recurse forever.
+        let mut seen = false;
+        for i in 0..=p.overflow as pgid_t {
+            let id = pgid + i;
+            if id >= high {
+                f(CheckError::OutOfBounds { pgid: id, high: high });
+            } else if !reachable.insert(id) {
+                f(CheckError::MultipleReferences(id));
+                seen = true;
+            }
+        }
+        if seen {
+            return;
+        }
+
+        // We should only encounter un-freed leaf and branch pages.
+        if freed.contains(&pgid) {
+            f(CheckError::ReachableFreed(pgid));
+        }
+        if (p.flags & BRANCH_PAGE_FLAG) != 0 {
+            let mut last: Option<&[u8]> = None;
+            for (i, e) in p.branch_page_elements()[..p.count as usize].iter().enumerate() {
+                let key = e.key();
+                if last.map_or(false, |last| key <= last) {
+                    f(CheckError::KeyOutOfOrder { pgid: pgid, index: i, key: key.to_vec() });
+                }
+                last = Some(key);
+                self.check_page(e.pgid, prev, children, reachable, freed, f);
+            }
+        } else if (p.flags & LEAF_PAGE_FLAG) != 0 {
+            for (i, e) in p.leaf_page_elements()[..p.count as usize].iter().enumerate() {
+                let key = e.key();
+                if prev.map_or(false, |prev| key <= prev) {
+                    f(CheckError::KeyOutOfOrder { pgid: pgid, index: i, key: key.to_vec() });
+                }
+                *prev = Some(key);
+                if (e.flags & BUCKET_LEAF_FLAG as u32) != 0 {
+                    let header = unsafe { ptr::read_unaligned(e.value().as_ptr() as *const _Bucket) };
+                    children.push(header.root);
+                }
+            }
+        } else {
+            f(CheckError::InvalidType { pgid: pgid, typ: p.typ() });
+        }
     }
 
     // delegate to freelist.
     // releases a page and its overflow for a given transaction id.
     // If the page is already free then a panic will occur.
-    pub fn free(&self, pgid: pgid_t) {
-        let db_borrow = self.db.borrow_mut();
-        let mut freelist_borrow = db_borrow.freelist.borrow_mut();
+    pub(crate) fn free(&self, pgid: pgid_t) {
+        let db = self.db();
+        let mut freelist = db.freelist.lock().unwrap();
+
+        freelist.free(self.meta.borrow().txid, self.page(pgid));
+    }
+
+    // allocate returns a contiguous block of memory starting at a given page.
+    pub(crate) fn allocate<'a>(&'a self, count: usize) -> Result<&'a mut Page, Error> {
+        let page_size = self.get_page_size();
+        let db = self.db();
+
+        // Use a run of pages from the freelist if one is available.
+        let mut id = db.freelist.lock().unwrap().allocate(count);
+
+        if id == 0 {
+            // Otherwise extend past the high water mark, resizing the mmap()
+            // if we're at the end.
+            let mut meta = self.meta.borrow_mut();
+            id = meta.pgid;
+            let minsz = (id as usize + count + 1) * page_size;
+            if minsz >= db.datasz() {
+                // Dereference all mmap references before unmapping.
+                if let Some(ref root) = *self.root.borrow() {
+                    root.dereference();
+                }
+                db.mmap(minsz)?;
+            }
+
+            // Move the page id high water mark. The file is grown to cover it on commit.
+            meta.pgid += count as pgid_t;
+        }
+
+        // Allocate a temporary buffer for the page.
+        let mut buf = vec![0u8; count * page_size];
+        let p = unsafe { &mut *(buf.as_mut_ptr() as *mut Page) };
+        p.id = id;
+        p.overflow = count as u32 - 1;
+        self.pages.borrow_mut().insert(id, buf);
+
+        // Update statistics.
+        let mut stats = self.stats.borrow_mut();
+        stats.page_count += 1;
+        stats.page_alloc += (count * page_size) as i32;
+
+        Ok(p)
+    }
+
+    // commit writes all changes to disk and updates the meta page.
+    // Returns an error if a disk write error occurs.
+    pub(crate) fn commit(&self) -> Result<(), Error> {
+        if self.managed.get() {
+            return Err(Error::ManagedTxCommitNotAllowed);
+        } else if self.db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        } else if !self.writable {
+            return Err(Error::TxNotWritable);
+        }
+        let root = self.root_bucket();
+        let opgid = self.meta.borrow().pgid;
+
+        // Rebalance nodes which have had deletions.
+        let start_time = Instant::now();
+        root.rebalance();
+        if self.stats.borrow().rebalance > 0 {
+            self.stats.borrow_mut().rebalance_time += start_time.elapsed();
+        }
+
+        // spill data onto dirty pages.
+        let start_time = Instant::now();
+        if let Err(e) = root.spill() {
+            self._rollback();
+            return Err(e);
+        }
+        self.stats.borrow_mut().spill_time += start_time.elapsed();
+
+        // Free the old root bucket. The whole header is copied so the root
+        // bucket's sequence is saved as well.
+        self.meta.borrow_mut().root = *root.bucket.borrow();
+
+        // Free the freelist and allocate new pages for it. This will overestimate
+        // the size of the freelist but not underestimate the size (which would be bad).
+        let db = self.db();
+        let freelist_pgid = self.meta.borrow().freelist;
+        self.free(freelist_pgid);
+        let count = db.freelist.lock().unwrap().size() / self.get_page_size() + 1;
+        let p = match self.allocate(count) {
+            Ok(p) => p,
+            Err(e) => {
+                self._rollback();
+                return Err(e);
+            },
+        };
+        db.freelist.lock().unwrap().write(p);
+        self.meta.borrow_mut().freelist = p.id;
+
+        // If the high water mark has moved up then attempt to grow the database.
+        let pgid = self.meta.borrow().pgid;
+        if pgid > opgid {
+            if let Err(e) = db.grow(pgid as usize * self.get_page_size()) {
+                self._rollback();
+                return Err(e);
+            }
+        }
+
+        // Write dirty pages to disk.
+        let start_time = Instant::now();
+        if let Err(e) = self.write() {
+            self._rollback();
+            return Err(e);
+        }
+
+        // Write meta to disk.
+        if let Err(e) = self.write_meta() {
+            self._rollback();
+            return Err(e);
+        }
+        self.stats.borrow_mut().write_time += start_time.elapsed();
+
+        // Finalize the transaction.
+        self.close();
+        Ok(())
+    }
+
+    // rollback closes the transaction and ignores all previous updates. Read-only
+    // transactions must be rolled back and not committed.
+    pub(crate) fn rollback(&self) -> Result<(), Error> {
+        if self.managed.get() {
+            return Err(Error::ManagedTxRollbackNotAllowed);
+        } else if self.db.borrow().is_none() {
+            return Err(Error::TxClosed);
+        }
+        self._rollback();
+        Ok(())
+    }
+
+    // discards dirty pages and node caches and reverts the pages freed
+    // by this transaction.
+    fn _rollback(&self) {
+        let db = match *self.db.borrow() {
+            None => return,
+            Some(ref db) => Arc::clone(db),
+        };
+        if self.writable && db.opened() {
+            let freelist_pgid = db.meta().freelist;
+            let mut freelist = db.freelist.lock().unwrap();
+            freelist.rollback(self.meta.borrow().txid);
+            freelist.reload(db.page(freelist_pgid));
+        }
+        self.close();
+    }
+
+    fn close(&self) {
+        // Clear all references. Buckets and nodes point back at the transaction
+        // so the caches are released explicitly.
+        if let Some(root) = self.root.borrow_mut().take() {
+            root.clear();
+        }
+        self.pages.borrow_mut().clear();
 
-        freelist_borrow.free(self.meta.pgid, Rc::clone(&self.page(pgid)));
+        // Release the writer lock or the reader slot so other transactions can proceed.
+        if let Some(db) = self.db.borrow_mut().take() {
+            if self.locked.replace(false) {
+                db.remove_tx(self);
+            }
+        }
     }
 
-    // allocate returns a continuous block of memory starting at a given page
-    pub fn allocate(&self, count: usize) -> Result<Rc<RefCell<Page>>, &'static str> {
-//        return Err("沟里个就算所以")
-        unimplemented!();
+    // write writes any dirty pages to disk.
+    fn write(&self) -> Result<(), Error> {
+        // Sort pages by id.
+        let mut pages: Vec<(pgid_t, Vec<u8>)> = self.pages.borrow_mut().drain().collect();
+        pages.sort_by_key(|&(id, _)| id);
+
+        // Write pages to disk in order.
+        let db = self.db();
+        for (id, buf) in pages {
+            let offset = id * db.page_size as u64;
+            db.write_at(&buf, offset)?;
+            self.stats.borrow_mut().write += 1;
+        }
+
+        db.sync()
+    }
+
+    // write_meta writes the meta to the disk.
+    fn write_meta(&self) -> Result<(), Error> {
+        // Create a temporary buffer for the meta page.
+        let db = self.db();
+        let mut buf = vec![0u8; db.page_size];
+        let id = {
+            let p = db.page_in_buffer(&mut buf, 0);
+            self.meta.borrow().write(p);
+            p.id
+        };
+
+        // Write the meta page to file.
+        db.write_at(&buf, id * db.page_size as u64)?;
+        db.sync()?;
+
+        // Update statistics.
+        self.stats.borrow_mut().write += 1;
+        Ok(())
     }
 
     pub fn get_page_size(&self) -> usize {
-        self.db.borrow().page_size
+        self.db().page_size
+    }
+}
+
+// A transaction that goes out of scope without being committed is rolled back
+// so its dirty pages and pending frees never leak into the database.
+impl Drop for Tx {
+    fn drop(&mut self) {
+        self._rollback();
+    }
+}
+
+// TxHandle owns a transaction started with DB::begin.
+// Buckets, cursors, keys and values borrow the handle, and commit and rollback
+// consume it, so the compiler rejects any use of them after the transaction closes.
+pub struct TxHandle {
+    tx: Box<Tx>,
+}
+
+impl TxHandle {
+    pub(crate) fn new(tx: Tx) -> TxHandle {
+        // Box the transaction before initializing it so the root bucket can point
+        // back at a stable address.
+        let tx = Box::new(tx);
+        tx.init();
+        TxHandle { tx: tx }
+    }
+
+    // commit writes all changes to disk and updates the meta page.
+    // Returns an error if a disk write error occurs, or if commit is
+    // called on a read-only transaction.
+    pub fn commit(self) -> Result<(), Error> {
+        self.tx.commit()
+    }
+
+    // rollback closes the transaction and ignores all previous updates.
+    pub fn rollback(self) -> Result<(), Error> {
+        self.tx.rollback()
+    }
+}
+
+impl Deref for TxHandle {
+    type Target = Tx;
+
+    fn deref(&self) -> &Tx {
+        &self.tx
     }
 }
 
@@ -176,3 +703,311 @@ impl Sub<TxStats> for TxStats {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use db::{DB, Meta, Options};
+    use errors::{CheckError, Error};
+    use db::tests::{tempfile, TestDB};
+    use page::{Page, LEAF_PAGE_FLAG, FREELIST_PAGE_FLAG};
+    use tx::Tx;
+    use std::sync::Arc;
+    use std::fs;
+
+    #[test]
+    fn tx_page_reads_mmap() {
+        let db = TestDB::new();
+        let tx = Tx::new(&db);
+        *tx.meta.borrow_mut() = db.meta();
+
+        assert_eq!({ tx.page(tx.meta.borrow().freelist).flags }, FREELIST_PAGE_FLAG);
+        let root = tx.page(tx.meta.borrow().root.root);
+        assert_eq!({ root.id }, 3);
+        assert_eq!({ root.flags }, LEAF_PAGE_FLAG);
+    }
+
+    #[test]
+    fn tx_allocate_extends_high_water_mark() {
+        let db = TestDB::new();
+        let tx = Tx::new(&db);
+        *tx.meta.borrow_mut() = db.meta();
+
+        let id = tx.allocate(1).unwrap().id;
+        assert_eq!(id, 4);
+        assert_eq!(tx.meta.borrow().pgid, 5);
+
+        let p = tx.allocate(3).unwrap();
+        assert_eq!({ p.id }, 5);
+        assert_eq!({ p.overflow }, 2);
+        assert_eq!(tx.meta.borrow().pgid, 8);
+        assert_eq!({ tx.page(5).overflow }, 2);
+
+        assert_eq!(tx.stats.borrow().page_count, 2);
+        assert_eq!(tx.stats.borrow().page_alloc, 4 * 4096);
+
+        // The file is only grown when the transaction commits.
+        assert_eq!(fs::metadata(&db.path).unwrap().len(), 4 * 4096);
+    }
+
+    #[test]
+    fn tx_allocate_from_freelist() {
+        let db = TestDB::new();
+        let tx = Tx::new(&db);
+        *tx.meta.borrow_mut() = db.meta();
+        {
+            let mut freelist = db.freelist.lock().unwrap();
+            freelist.ids = vec![10, 11, 12];
+            freelist.reindex();
+        }
+
+        assert_eq!({ tx.allocate(2).unwrap().id }, 10);
+        assert_eq!(tx.meta.borrow().pgid, 4);
+        assert_eq!(db.freelist.lock().unwrap().ids, vec![12]);
+        assert!(!db.freelist.lock().unwrap().freed(10));
+    }
+
+    #[test]
+    fn tx_allocate_remaps() {
+        let db = TestDB::new();
+        let tx = Tx::new(&db);
+        *tx.meta.borrow_mut() = db.meta();
+        assert_eq!(db.datasz(), 32 * 1024);
+
+        assert_eq!({ tx.allocate(20).unwrap().id }, 4);
+        assert_eq!(db.datasz(), 128 * 1024);
+        assert_eq!({ tx.page(3).id }, 3);
+    }
+
+    #[test]
+    fn tx_page_prefers_dirty_page() {
+        let db = TestDB::new();
+        let tx = Tx::new(&db);
+
+        let mut buf = vec![0u8; 4096];
+        {
+            let p = unsafe { &mut *(buf.as_mut_ptr() as *mut Page) };
+            p.id = 3;
+            p.flags = LEAF_PAGE_FLAG;
+            p.count = 7;
+        }
+        tx.pages.borrow_mut().insert(3, buf);
+
+        assert_eq!({ tx.page(3).count }, 7);
+        assert_eq!({ db.page(3).count }, 0);
+    }
+
+    #[test]
+    fn tx_check() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let root = tx.root_bucket();
+            let widgets = root.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                widgets.put(format!("{:08}", i).as_bytes(), &[0; 64])?;
+            }
+            widgets.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            root.create_bucket(b"empty")?;
+            Ok(())
+        }).unwrap();
+
+        DB::view(&db, |tx| {
+            tx.check(|e| panic!("{}", e))?;
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn tx_check_reports_all_problems() {
+        let db = TestDB::new();
+        let tx = DB::begin(&db, true).unwrap();
+
+        // Free the root page while it's still reachable and leak a new page.
+        tx.free(3);
+        assert_eq!({ tx.allocate(1).unwrap().id }, 4);
+
+        let mut errors = vec![];
+        tx.check(|e| errors.push(e)).unwrap();
+        assert_eq!(errors, vec![CheckError::ReachableFreed(3), CheckError::UnreachableUnfreed(4)]);
+        assert_eq!(errors[0].to_string(), "page 3: reachable freed");
+    }
+
+    #[test]
+    fn tx_check_read_only_snapshot() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let widgets = tx.root_bucket().create_bucket(b"widgets")?;
+            for i in 0..100u32 {
+                widgets.put(format!("{:08}", i).as_bytes(), &[0; 64])?;
+            }
+            Ok(())
+        }).unwrap();
+
+        // A writer frees pages the reader can still reach and commits.
+        let tx = DB::begin(&db, false).unwrap();
+        DB::update(&db, |tx| tx.root_bucket().delete_bucket(b"widgets")).unwrap();
+        tx.check(|e| panic!("{}", e)).unwrap();
+
+        Tx::rollback(&tx).unwrap();
+        assert_eq!(tx.check(|_| ()), Err(Error::TxClosed));
+    }
+
+    #[test]
+    fn tx_copy_file() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            let widgets = tx.root_bucket().create_bucket(b"widgets")?;
+            for i in 0..500u32 {
+                widgets.put(format!("{:08}", i).as_bytes(), &[1; 100])?;
+            }
+            Ok(())
+        }).unwrap();
+
+        // Take the copy from a read transaction while a writer keeps going.
+        let copy = tempfile();
+        let tx = DB::begin(&db, false).unwrap();
+        DB::update(&db, |tx| tx.root_bucket().bucket(b"widgets").unwrap().put(b"new", b"value")).unwrap();
+        tx.copy_file(&copy).unwrap();
+        assert_eq!(fs::metadata(&copy).unwrap().len(), tx.size());
+        let txid = tx.meta.borrow().txid;
+        tx.rollback().unwrap();
+
+        // The copy opens at the snapshot the read transaction saw.
+        let backup = Arc::new(DB::open(&copy, Options::default()).unwrap());
+        assert_eq!(backup.meta().txid, txid);
+        DB::view(&backup, |tx| {
+            tx.check(|e| panic!("{}", e))?;
+            let widgets = tx.root_bucket().bucket(b"widgets").unwrap();
+            assert_eq!(widgets.get(b"00000499"), Some(&[1; 100][..]));
+            assert_eq!(widgets.get(b"new"), None);
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn tx_write_to() {
+        let db = TestDB::new();
+        let tx = DB::begin(&db, false).unwrap();
+        let mut buf = vec![];
+        assert_eq!(tx.write_to(&mut buf).unwrap(), 4 * 4096);
+        assert_eq!(buf.len() as u64, tx.size());
+
+        // Both meta pages are valid and the data pages match the file.
+        let meta0 = Meta::read(unsafe { &*(buf.as_ptr() as *const Page) });
+        let meta1 = Meta::read(unsafe { &*(buf[4096..].as_ptr() as *const Page) });
+        assert_eq!(meta0.txid, 0);
+        assert_eq!(meta1.txid, 1);
+        assert_eq!(&buf[2 * 4096..], &fs::read(&db.path).unwrap()[2 * 4096..4 * 4096]);
+
+        Tx::rollback(&tx).unwrap();
+        assert_eq!(tx.write_to(&mut buf).err(), Some(Error::TxClosed));
+    }
+
+    #[test]
+    fn tx_commit_writes_meta() {
+        let mut db = TestDB::new();
+        let tx = DB::begin(&db, true).unwrap();
+        assert_eq!(tx.meta.borrow().txid, 2);
+
+        let root = tx.root_bucket();
+        let n = root.node(3, None);
+        n.borrow_mut().put(b"foo", b"foo", Some(&b"bar"[..]), 0, 0);
+        Tx::commit(&tx).unwrap();
+        assert!(tx.stats.borrow().spill > 0);
+        assert!(tx.stats.borrow().write > 0);
+        assert!(tx.db.borrow().is_none());
+        drop(root);
+
+        // The file was grown once to cover the new high water mark.
+        assert_eq!(fs::metadata(&db.path).unwrap().len(), db.meta().pgid * 4096);
+
+        // The new meta page points at the rewritten root and freelist.
+        db.reopen();
+        let meta = db.meta();
+        assert_eq!(meta.txid, 2);
+        assert!(meta.root.root >= 4);
+        assert!(meta.freelist >= 4);
+        let p = db.page(meta.root.root);
+        assert_eq!({ p.flags }, LEAF_PAGE_FLAG);
+        assert_eq!({ p.count }, 1);
+        assert_eq!({ db.page(meta.freelist).flags }, FREELIST_PAGE_FLAG);
+
+        // The old root and freelist pages are pending for the committed transaction.
+        assert_eq!(db.freelist.lock().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn tx_commit_not_writable() {
+        let db = TestDB::new();
+        let tx = DB::begin(&db, false).unwrap();
+        assert_eq!(tx.commit(), Err(Error::TxNotWritable));
+    }
+
+    #[test]
+    fn tx_commit_closed() {
+        let db = TestDB::new();
+        let tx = DB::begin(&db, true).unwrap();
+        Tx::commit(&tx).unwrap();
+        assert_eq!(Tx::commit(&tx), Err(Error::TxClosed));
+    }
+
+    #[test]
+    fn tx_rollback_reverts_pending() {
+        let db = TestDB::new();
+        let tx = DB::begin(&db, true).unwrap();
+        tx.free(3);
+        tx.allocate(2).unwrap();
+        assert_eq!(db.freelist.lock().unwrap().pending_count(), 1);
+
+        Tx::rollback(&tx).unwrap();
+        assert_eq!(db.freelist.lock().unwrap().pending_count(), 0);
+        assert!(tx.pages.borrow().is_empty());
+        assert!(tx.root.borrow().is_none());
+        assert_eq!(Tx::rollback(&tx), Err(Error::TxClosed));
+        assert_eq!(db.meta().txid, 1);
+    }
+
+    #[test]
+    fn tx_rollback_reloads_freelist() {
+        let db = TestDB::new();
+        DB::update(&db, |tx| {
+            for i in 0..1000u32 {
+                tx.root_bucket().put(format!("{:08}", i).as_bytes(), &[0; 64])?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::update(&db, |tx| {
+            for i in 0..1000u32 {
+                tx.root_bucket().delete(format!("{:08}", i).as_bytes())?;
+            }
+            Ok(())
+        }).unwrap();
+        DB::update(&db, |tx| tx.root_bucket().put(b"foo", b"bar")).unwrap();
+        assert!(!db.freelist.lock().unwrap().ids.is_empty());
+
+        // Rolling back a writer restores the freelist without duplicating ids.
+        DB::begin(&db, true).unwrap().rollback().unwrap();
+        let ids = db.freelist.lock().unwrap().ids.clone();
+        let mut unique = ids.clone();
+        unique.dedup();
+        assert_eq!(ids, unique);
+
+        let tx = DB::begin(&db, true).unwrap();
+        let a = tx.allocate(1).unwrap().id;
+        let b = tx.allocate(1).unwrap().id;
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn tx_rollback_on_drop() {
+        let db = TestDB::new();
+        {
+            let tx = DB::begin(&db, true).unwrap();
+            let root = tx.root_bucket();
+            root.node(3, None).borrow_mut().put(b"foo", b"foo", Some(&b"bar"[..]), 0, 0);
+            tx.free(2);
+        }
+        assert_eq!(db.freelist.lock().unwrap().pending_count(), 0);
+        assert_eq!(db.meta().txid, 1);
+        assert_eq!({ db.page(3).count }, 0);
+    }
+}